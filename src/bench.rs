@@ -0,0 +1,272 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+use crate::event_input_device::EventDevice;
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::matix_mapper::{MatrixMapper, MatrixPosition};
+use crate::key_event::{KeyEvent, MyTime};
+use crate::keyboard::{Keyboard, Operation};
+
+/// Output key presses further than this from an input key press are not
+/// counted as its keystroke
+const MATCH_WINDOW: Duration = Duration::from_secs(1);
+
+/// Durations measured for one step
+pub struct Samples {
+    name: &'static str,
+    durations: Vec<Duration>,
+}
+
+impl Samples {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            durations: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, duration: Duration) {
+        self.durations.push(duration);
+    }
+
+    pub fn extend<I: IntoIterator<Item = Duration>>(&mut self, durations: I) {
+        self.durations.extend(durations);
+    }
+
+    pub fn len(&self) -> usize {
+        self.durations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.durations.is_empty()
+    }
+
+    /// The smallest sample that `percent` of the samples are at or below
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        let mut sorted = self.durations.clone();
+        sorted.sort();
+
+        let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+impl std::fmt::Display for Samples {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |percent| {
+            self.percentile(percent)
+                .map(|d| format!("{:.2}ms", d.as_secs_f64() * 1000.0))
+                .unwrap_or_default()
+        };
+
+        if self.is_empty() {
+            return write!(f, "{}: no samples", self.name);
+        }
+
+        write!(
+            f,
+            "{}: {} samples, min {}, p50 {}, p90 {}, p99 {}, max {}",
+            self.name,
+            self.len(),
+            ms(0.0),
+            ms(50.0),
+            ms(90.0),
+            ms(99.0),
+            ms(100.0)
+        )
+    }
+}
+
+/// Pairs the presses forwarded to the controller with the keystrokes it types
+/// for them, by key code. Presses typing nothing, like layer keys, are dropped
+/// once they are too old to be matched.
+#[derive(Default)]
+struct KeystrokeMatcher {
+    presses: HashMap<KeyCode, VecDeque<MyTime>>,
+}
+
+impl KeystrokeMatcher {
+    fn press(&mut self, code: KeyCode, time: MyTime) {
+        self.presses.entry(code).or_default().push_back(time);
+    }
+
+    /// Whether some press may still get its keystroke
+    fn pending(&self) -> bool {
+        self.presses.values().any(|presses| !presses.is_empty())
+    }
+
+    /// The latency of the oldest press of `code` typed at `time`, if any.
+    /// A keystroke older than every press waiting for one was not typed for
+    /// them and is dropped.
+    fn keystroke(&mut self, code: KeyCode, time: MyTime) -> Option<Duration> {
+        let presses = self.presses.get_mut(&code)?;
+
+        while let Some(press) = presses.pop_front() {
+            match time.checked_since(press) {
+                None => {
+                    presses.push_front(press);
+                    return None;
+                }
+                Some(latency) if latency < MATCH_WINDOW => return Some(latency),
+                // Typed nothing, or its keystroke was missed
+                Some(_) => {}
+            }
+        }
+
+        None
+    }
+}
+
+/// Times `count` layer queries, which have no effect on the controller
+pub fn round_trips(keyboard: &Keyboard, count: usize) -> anyhow::Result<Vec<Samples>> {
+    let mut write = Samples::new("HID write");
+    let mut round_trip = Samples::new("HID round trip");
+
+    for _ in 0..count {
+        let start = Instant::now();
+        keyboard.write(Operation::GetLayer)?;
+        write.push(start.elapsed());
+        keyboard.read_response()?;
+        round_trip.push(start.elapsed());
+    }
+
+    Ok(vec![write, round_trip])
+}
+
+/// Forwards the key presses of `device` like the daemon does, timing them from
+/// the kernel's timestamp until they are written to the controller and until
+/// it responds. When the controller types on `output`, also times each press
+/// until the keystroke of the same key shows up there. Stops after `count`
+/// presses, once every key is released and the last keystrokes came in.
+pub fn forwarding(
+    keyboard: &Keyboard,
+    device: &EventDevice,
+    mapper: &MatrixMapper,
+    output: Option<EventDevice>,
+    count: usize,
+) -> anyhow::Result<Vec<Samples>> {
+    let mut written = Samples::new("evdev to HID write");
+    let mut acknowledged = Samples::new("evdev to HID response");
+    let mut end_to_end = Samples::new("press to keystroke");
+
+    let (tx, rx) = channel::<(KeyCode, MyTime)>();
+    let timing_output = output.is_some();
+    if let Some(output) = output {
+        std::thread::spawn(move || loop {
+            match output.next() {
+                Ok(KeyEvent::Press(code, false, time)) => {
+                    if tx.send((code, time)).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        });
+    }
+
+    let mut held: HashMap<KeyCode, Vec<MatrixPosition>> = HashMap::new();
+    let mut matcher = KeystrokeMatcher::default();
+
+    while written.len() < count || !held.is_empty() {
+        match device.next()? {
+            KeyEvent::Press(code, false, time) if written.len() < count => {
+                let positions = mapper.positions(code).to_vec();
+                if positions.is_empty() {
+                    continue;
+                }
+
+                for pos in &positions {
+                    keyboard.write(Operation::UpdateMatrix(true, pos.row, pos.col))?;
+                }
+                written.push(time.elapsed());
+                for _ in &positions {
+                    keyboard.read_response()?;
+                }
+                acknowledged.push(time.elapsed());
+
+                if timing_output {
+                    matcher.press(code, time);
+                }
+                held.insert(code, positions);
+            }
+            KeyEvent::Release(code, _) => {
+                for pos in held.remove(&code).unwrap_or_default() {
+                    keyboard.send_message(Operation::UpdateMatrix(false, pos.row, pos.col))?;
+                }
+            }
+            _ => {}
+        }
+
+        while let Ok((code, time)) = rx.try_recv() {
+            end_to_end.extend(matcher.keystroke(code, time));
+        }
+    }
+
+    while matcher.pending() {
+        match rx.recv_timeout(MATCH_WINDOW) {
+            Ok((code, time)) => end_to_end.extend(matcher.keystroke(code, time)),
+            Err(_) => break,
+        }
+    }
+
+    let mut samples = vec![written, acknowledged];
+    if timing_output {
+        samples.push(end_to_end);
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::bench::{KeystrokeMatcher, Samples};
+    use crate::key_event::key_code::KeyCode;
+    use crate::test_fixtures::at;
+
+    #[test]
+    fn test_percentiles() {
+        let mut samples = Samples::new("test");
+        assert_eq!(samples.percentile(50.0), None);
+        assert_eq!(samples.to_string(), "test: no samples");
+
+        for ms in (1..=100).rev() {
+            samples.push(Duration::from_millis(ms));
+        }
+
+        assert_eq!(samples.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(samples.percentile(50.0), Some(Duration::from_millis(50)));
+        assert_eq!(samples.percentile(99.0), Some(Duration::from_millis(99)));
+        assert_eq!(samples.percentile(100.0), Some(Duration::from_millis(100)));
+        assert_eq!(
+            samples.to_string(),
+            "test: 100 samples, min 1.00ms, p50 50.00ms, p90 90.00ms, p99 99.00ms, max 100.00ms"
+        );
+    }
+
+    #[test]
+    fn test_keystroke_matching() {
+        let mut matcher = KeystrokeMatcher::default();
+        // A layer key typing nothing, then A
+        matcher.press(KeyCode::FN, at(1000));
+        matcher.press(KeyCode::A, at(1100));
+        assert!(matcher.pending());
+
+        // Typed before the press, so not for it
+        assert_eq!(matcher.keystroke(KeyCode::A, at(1050)), None);
+        assert_eq!(matcher.keystroke(KeyCode::B, at(1105)), None);
+        assert_eq!(
+            matcher.keystroke(KeyCode::A, at(1108)),
+            Some(Duration::from_millis(8))
+        );
+        assert_eq!(matcher.keystroke(KeyCode::A, at(1110)), None);
+
+        // Too late to be the keystroke of this press
+        matcher.press(KeyCode::A, at(2000));
+        assert_eq!(matcher.keystroke(KeyCode::A, at(3500)), None);
+        assert!(matcher.presses[&KeyCode::A].is_empty());
+    }
+}
@@ -0,0 +1,825 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::key_event::builtin_layouts;
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::kle;
+use crate::key_event::matix_mapper::Layout;
+use crate::key_event::qmk_info::{KeyIndex, QmkInfo};
+use crate::key_event::relative_axis::RelativeAxis;
+
+const CONFIG_FILE_NAMES: [&str; 3] = ["config.toml", "config.yaml", "config.yml"];
+
+/// Windows of password managers and prompts, where statistics pause by default
+const DEFAULT_PAUSE_CLASSES: [&str; 6] = [
+    "pinentry",
+    "Pinentry",
+    "Gcr-prompter",
+    "polkit",
+    "Polkit",
+    "KeePassXC",
+];
+const DEFAULT_PAUSE_TITLES: [&str; 6] = [
+    "Password",
+    "password",
+    "Passphrase",
+    "passphrase",
+    "Authentication",
+    "authentication",
+];
+
+/// Daemon configuration, read from `$XDG_CONFIG_HOME/qmk-virtual-keyboard/config.toml`
+/// (or `config.yaml`). Every field is optional and command line flags take
+/// precedence over whatever is set here.
+///
+/// ```toml
+/// devices = ["/dev/input/by-id/usb-Some_Keyboard-event-kbd"]
+/// clone_identity = true
+/// layouts = ["layout.json", { qmk = "info.json", layout = "LAYOUT_ansi", keys = "keys.json" }]
+/// layers = ["Qwerty", "Workman", "Sys"]
+/// panic_chord = ["LEFTCTRL", "RIGHTCTRL", "ESC"]
+///
+/// pipeline = [{ stage = "remap", keys = { CAPSLOCK = "ESC" } }, { stage = "debounce" }, { stage = "panic_chord" }]
+///
+/// [chatter]
+/// threshold = 30
+/// overrides = { SPACE = 50 }
+///
+/// [controller]
+/// vendor_id = 0x4b41
+/// product_id = 0x504d
+/// rows = 8
+/// cols = 24
+///
+/// [integrations.i3]
+/// default_layer = 0
+/// rules = [{ class = "steam", layer = 5 }]
+///
+/// [[mice]]
+/// device = "/dev/input/by-id/usb-Some_Mouse-event-mouse"
+/// buttons = ["BTN_SIDE", "BTN_EXTRA"]
+///
+/// [software]
+/// keymap = "keymap.json"
+///
+/// [metrics]
+/// listen = "127.0.0.1:9102"
+///
+/// [stats]
+/// file = "stats.json"
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub controller: ControllerConfig,
+    /// Input devices that are grabbed and forwarded to the controller
+    pub devices: Vec<String>,
+    /// Gives the virtual keyboards of the daemon (software keymap, host
+    /// reports and passthrough) the name (with a suffix), ids and
+    /// capabilities of the first device, so the desktop configures them alike
+    pub clone_identity: bool,
+    /// Mice whose selected buttons are forwarded to the controller
+    pub mice: Vec<MouseConfig>,
+    /// Layout files, relative paths are resolved from the config file directory
+    pub layouts: Vec<LayoutFile>,
+    /// Layer names, indexed by layer number. Empty names are ignored
+    pub layers: Vec<String>,
+    /// Holding all of these keys at once stops the daemon, releasing the grab
+    pub panic_chord: Vec<KeyCode>,
+    /// Drops the double presses of worn switches, off unless set
+    pub chatter: Option<ChatterConfig>,
+    /// Stages input events go through before reaching the controller, see
+    /// `stages` for the default
+    pub pipeline: Option<Vec<StageConfig>>,
+    pub integrations: IntegrationsConfig,
+    /// Runs a keymap on the host instead of forwarding to the controller
+    pub software: Option<SoftwareConfig>,
+    pub metrics: MetricsConfig,
+    /// Records key usage while forwarding, off unless set
+    pub stats: Option<StatsConfig>,
+}
+
+/// A layout in this application's own format, a layout macro from a QMK
+/// `info.json` / `keyboard.json` with the evdev keys driving each of its keys,
+/// keyboard-layout-editor.com raw data with `"row,col"` legends, or one of the
+/// built-in layouts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LayoutFile {
+    Layout(PathBuf),
+    Qmk {
+        qmk: PathBuf,
+        layout: Option<String>,
+        keys: PathBuf,
+    },
+    Kle {
+        kle: PathBuf,
+    },
+    Builtin {
+        builtin: String,
+    },
+}
+
+impl LayoutFile {
+    pub fn load(&self) -> anyhow::Result<Layout> {
+        match self {
+            Self::Layout(path) => Layout::from_file(path),
+            Self::Qmk { qmk, layout, keys } => {
+                QmkInfo::from_file(qmk)?.to_layout(layout.as_deref(), &KeyIndex::from_file(keys)?)
+            }
+            Self::Kle { kle } => kle::load(kle),
+            Self::Builtin { builtin } => builtin_layouts::builtin(builtin).ok_or_else(|| {
+                anyhow!(
+                    "Unknown built-in layout {builtin}, use one of {}",
+                    builtin_layouts::NAMES.join(", ")
+                )
+            }),
+        }
+    }
+
+    /// Matrix size declared by the layout source, only QMK files have one
+    pub fn matrix_size(&self) -> Option<(u8, u8)> {
+        match self {
+            Self::Qmk { qmk, .. } => QmkInfo::from_file(qmk)
+                .ok()?
+                .matrix_size
+                .map(|size| (size.rows, size.cols)),
+            _ => None,
+        }
+    }
+
+    /// Every file this layout is read from
+    pub fn paths(&self) -> Vec<&Path> {
+        match self {
+            Self::Layout(path) => vec![path],
+            Self::Qmk { qmk, keys, .. } => vec![qmk, keys],
+            Self::Kle { kle } => vec![kle],
+            Self::Builtin { .. } => vec![],
+        }
+    }
+
+    fn relative_to(&self, dir: &Path) -> Self {
+        match self {
+            Self::Layout(path) => Self::Layout(dir.join(path)),
+            Self::Qmk { qmk, layout, keys } => Self::Qmk {
+                qmk: dir.join(qmk),
+                layout: layout.clone(),
+                keys: dir.join(keys),
+            },
+            Self::Kle { kle } => Self::Kle { kle: dir.join(kle) },
+            Self::Builtin { .. } => self.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for LayoutFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Layout(path) => write!(f, "{}", path.display()),
+            Self::Qmk { qmk, layout, .. } => match layout {
+                Some(layout) => write!(f, "{} ({layout})", qmk.display()),
+                None => write!(f, "{}", qmk.display()),
+            },
+            Self::Kle { kle } => write!(f, "{}", kle.display()),
+            Self::Builtin { builtin } => write!(f, "built-in {builtin}"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControllerConfig {
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub usage_page: Option<u16>,
+    pub usage: Option<u16>,
+    /// Number of matrix rows in the firmware, used to validate layouts
+    pub rows: Option<u8>,
+    /// Number of matrix columns in the firmware, used to validate layouts
+    pub cols: Option<u8>,
+    /// Has the controller send its keyboard reports back over raw HID, to be
+    /// typed through uinput, instead of acting as a USB keyboard
+    pub host_reports: bool,
+}
+
+/// A mouse that is grabbed so some of its buttons can be forwarded to the
+/// controller. Everything else it sends goes through a virtual mouse.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MouseConfig {
+    pub device: String,
+    /// Buttons forwarded to the controller, like `BTN_SIDE` and `BTN_EXTRA`
+    #[serde(default)]
+    pub buttons: Vec<KeyCode>,
+    /// Relative axes forwarded to the controller, like `REL_WHEEL`. The
+    /// layout maps them to encoders
+    #[serde(default)]
+    pub axes: Vec<RelativeAxis>,
+    /// Gives the virtual mouse the name (with a suffix) and ids of the real
+    /// one, so per-device settings of the desktop apply to it too
+    #[serde(default)]
+    pub clone_identity: bool,
+}
+
+/// A QMK `keymap.json` run by the daemon itself, for when no controller is
+/// plugged in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SoftwareConfig {
+    /// Keymap file, relative paths are resolved from the config file directory
+    pub keymap: PathBuf,
+    /// Milliseconds a mod-tap or layer-tap key can be held and still be a tap
+    pub tapping_term: Option<u64>,
+}
+
+/// A stage of the forwarding pipeline. Stages without settings of their own
+/// use the config section of the same feature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StageConfig {
+    /// Turns keys into others
+    Remap { keys: HashMap<KeyCode, KeyCode> },
+    /// Drops keys
+    Filter { keys: Vec<KeyCode> },
+    /// Drops switch chatter, as set in `chatter`
+    Debounce,
+    /// Records key usage, as set in `stats`
+    Stats,
+    /// Types keys on a virtual keyboard instead of sending them to the
+    /// controller, by default the keys missing from the layout
+    Passthrough {
+        #[serde(default)]
+        keys: Vec<KeyCode>,
+    },
+    /// Stops the daemon when `panic_chord` is held
+    PanicChord,
+}
+
+impl StageConfig {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Remap { .. } => "remap",
+            Self::Filter { .. } => "filter",
+            Self::Debounce => "debounce",
+            Self::Stats => "stats",
+            Self::Passthrough { .. } => "passthrough",
+            Self::PanicChord => "panic_chord",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatterConfig {
+    /// Milliseconds under which a press or a release that is undone is
+    /// chatter, each press and release is held back this long
+    pub threshold: u64,
+    /// Thresholds of particular keys, 0 to let every press of a key through
+    #[serde(default)]
+    pub overrides: HashMap<KeyCode, u64>,
+}
+
+/// Where the daemon exposes its metrics in the Prometheus text format
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address serving them over HTTP at `/metrics`
+    pub listen: Option<String>,
+    /// File kept up to date for node_exporter's textfile collector, relative
+    /// paths are resolved from the config file directory
+    pub textfile: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatsConfig {
+    /// Statistics file, relative paths are resolved from the config file
+    /// directory
+    pub file: PathBuf,
+    /// Windows during whose focus nothing is recorded, password prompts by
+    /// default. Recording also stops when i3 cannot tell which window has the
+    /// focus, unless this is empty.
+    pub pause_on: Option<Vec<WindowMatch>>,
+}
+
+impl StatsConfig {
+    pub fn pause_rules(&self) -> Vec<WindowMatch> {
+        match &self.pause_on {
+            Some(rules) => rules.clone(),
+            None => DEFAULT_PAUSE_CLASSES
+                .iter()
+                .map(|class| WindowMatch {
+                    class: Some(class.to_string()),
+                    title: None,
+                })
+                .chain(DEFAULT_PAUSE_TITLES.iter().map(|title| WindowMatch {
+                    class: None,
+                    title: Some(title.to_string()),
+                }))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntegrationsConfig {
+    pub i3: Option<I3Config>,
+}
+
+/// Changes the keyboard layer according to the focused i3 window
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct I3Config {
+    /// Layer used when no rule matches the focused window
+    pub default_layer: Option<u8>,
+    pub rules: Vec<WindowRule>,
+}
+
+/// Matches windows whose class and title contain the given strings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowRule {
+    pub class: Option<String>,
+    pub title: Option<String>,
+    pub layer: u8,
+}
+
+impl WindowRule {
+    pub fn matches(&self, class: Option<&str>, title: Option<&str>) -> bool {
+        window_matches(&self.class, &self.title, class, title)
+    }
+}
+
+/// Matches windows whose class and title contain the given strings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowMatch {
+    pub class: Option<String>,
+    pub title: Option<String>,
+}
+
+impl WindowMatch {
+    pub fn matches(&self, class: Option<&str>, title: Option<&str>) -> bool {
+        window_matches(&self.class, &self.title, class, title)
+    }
+}
+
+/// At least one of the patterns must be set, and each one set must be
+/// contained in the window's
+fn window_matches(
+    class_pattern: &Option<String>,
+    title_pattern: &Option<String>,
+    class: Option<&str>,
+    title: Option<&str>,
+) -> bool {
+    fn contains(pattern: &Option<String>, value: Option<&str>) -> bool {
+        match pattern {
+            Some(pattern) => value.is_some_and(|v| v.contains(pattern.as_str())),
+            None => true,
+        }
+    }
+
+    (class_pattern.is_some() || title_pattern.is_some())
+        && contains(class_pattern, class)
+        && contains(title_pattern, title)
+}
+
+impl I3Config {
+    /// Layer that should be active for the given window, if any
+    pub fn layer_for(&self, class: Option<&str>, title: Option<&str>) -> Option<u8> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(class, title))
+            .map(|rule| rule.layer)
+            .or(self.default_layer)
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("qmk-virtual-keyboard"))
+}
+
+impl Config {
+    /// Every config file looked for in the config directory, in order
+    pub fn default_paths() -> Vec<PathBuf> {
+        let Some(dir) = config_dir() else {
+            return Vec::new();
+        };
+
+        CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .collect()
+    }
+
+    /// The first existing config file in the config directory
+    pub fn default_path() -> Option<PathBuf> {
+        Self::default_paths().into_iter().find(|path| path.exists())
+    }
+
+    pub fn from_str(contents: &str, format: &str) -> anyhow::Result<Self> {
+        match format {
+            "toml" => Ok(toml::from_str(contents)?),
+            "yaml" | "yml" => Ok(serde_yaml::from_str(contents)?),
+            _ => bail!("unsupported config format {format:?}, use toml or yaml"),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let format = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .ok_or_else(|| anyhow!("cannot tell the format of {}", path.display()))?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("could not read {}: {e}", path.display()))?;
+
+        let mut config = Self::from_str(&contents, format)
+            .map_err(|e| anyhow!("invalid config {}: {e}", path.display()))?;
+
+        if let Some(dir) = path.parent() {
+            config.layouts = config.layouts.iter().map(|l| l.relative_to(dir)).collect();
+            if let Some(software) = &mut config.software {
+                software.keymap = dir.join(&software.keymap);
+            }
+            if let Some(textfile) = &mut config.metrics.textfile {
+                *textfile = dir.join(&textfile);
+            }
+            if let Some(stats) = &mut config.stats {
+                stats.file = dir.join(&stats.file);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Loads the given config file, or the default one if it exists
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        match path.map(Path::to_path_buf).or_else(Self::default_path) {
+            Some(path) => Self::from_file(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// The configured pipeline, or the one made of the `chatter`, `stats` and
+    /// `panic_chord` features that are set, in that order. A pipeline leaving
+    /// out a set `panic_chord`, or changing its keys before it, is refused so
+    /// the chord always stops the daemon.
+    pub fn stages(&self) -> anyhow::Result<Vec<StageConfig>> {
+        let Some(stages) = &self.pipeline else {
+            return Ok([
+                self.chatter.as_ref().map(|_| StageConfig::Debounce),
+                self.stats.as_ref().map(|_| StageConfig::Stats),
+                (!self.panic_chord.is_empty()).then_some(StageConfig::PanicChord),
+            ]
+            .into_iter()
+            .flatten()
+            .collect());
+        };
+
+        for stage in stages {
+            match stage {
+                StageConfig::Debounce if self.chatter.is_none() => {
+                    bail!("the debounce stage needs a chatter section")
+                }
+                StageConfig::Stats if self.stats.is_none() => {
+                    bail!("the stats stage needs a stats section")
+                }
+                StageConfig::PanicChord if self.panic_chord.is_empty() => {
+                    bail!("the panic_chord stage needs a panic_chord")
+                }
+                _ => {}
+            }
+        }
+
+        if self.panic_chord.is_empty() {
+            return Ok(stages.clone());
+        }
+
+        let Some(chord_stage) = stages.iter().position(|s| *s == StageConfig::PanicChord) else {
+            bail!("panic_chord is set but no panic_chord stage of the pipeline uses it")
+        };
+        for stage in &stages[..chord_stage] {
+            let changed = match stage {
+                StageConfig::Remap { keys } => self
+                    .panic_chord
+                    .iter()
+                    .find(|key| keys.get(key).is_some_and(|to| to != *key)),
+                StageConfig::Filter { keys } | StageConfig::Passthrough { keys } => {
+                    self.panic_chord.iter().find(|key| keys.contains(key))
+                }
+                _ => None,
+            };
+            if let Some(key) = changed {
+                bail!(
+                    "the {} stage takes {} away before the panic_chord stage, so the chord cannot be pressed",
+                    stage.name(),
+                    key.as_ref()
+                );
+            }
+        }
+
+        Ok(stages.clone())
+    }
+
+    pub fn layer_name(&self, layer: u8) -> Option<&str> {
+        self.layers
+            .get(layer as usize)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Firmware matrix size, from the controller settings or a QMK layout
+    pub fn matrix_size(&self) -> Option<(u8, u8)> {
+        match (self.controller.rows, self.controller.cols) {
+            (Some(rows), Some(cols)) => Some((rows, cols)),
+            _ => self.layouts.iter().find_map(LayoutFile::matrix_size),
+        }
+    }
+
+    /// Loads every configured layout into a single one, or the default
+    /// built-in layout when none is configured
+    pub fn load_layouts(&self) -> anyhow::Result<Layout> {
+        if self.layouts.is_empty() {
+            info!(
+                "No layout configured, using the built-in {} layout",
+                builtin_layouts::DEFAULT
+            );
+            return LayoutFile::Builtin {
+                builtin: builtin_layouts::DEFAULT.to_string(),
+            }
+            .load();
+        }
+
+        let mut layout = Layout::default();
+
+        for file in &self.layouts {
+            let mut l = file
+                .load()
+                .map_err(|e| anyhow!("invalid layout {file}: {e}"))?;
+            layout.layout.append(&mut l.layout);
+            layout.encoders.append(&mut l.encoders);
+        }
+
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use crate::config::{
+        ChatterConfig, Config, LayoutFile, MetricsConfig, MouseConfig, SoftwareConfig, StageConfig,
+        StatsConfig, WindowMatch,
+    };
+    use crate::key_event::key_code::KeyCode;
+
+    #[test]
+    fn test_toml_and_yaml() {
+        let toml = Config::from_str(
+            r#"
+            devices = ["/dev/input/event3"]
+            clone_identity = true
+            layouts = ["layout.json", { qmk = "info.json", keys = "keys.json" }]
+            layers = ["Qwerty", "", "Sys"]
+            panic_chord = ["LEFTCTRL", "ESC"]
+
+            [chatter]
+            threshold = 30
+            overrides = { SPACE = 50 }
+
+            [controller]
+            vendor_id = 0x4b41
+            host_reports = true
+
+            [integrations.i3]
+            default_layer = 0
+            rules = [{ class = "steam", layer = 5 }]
+
+            [[mice]]
+            device = "/dev/input/event5"
+            buttons = ["BTN_SIDE", "BTN_EXTRA"]
+            clone_identity = true
+
+            [software]
+            keymap = "keymap.json"
+            tapping_term = 175
+
+            [metrics]
+            listen = "127.0.0.1:9102"
+
+            [stats]
+            file = "stats.json"
+            pause_on = [{ title = "Vault" }]
+            "#,
+            "toml",
+        )
+        .unwrap();
+
+        let yaml = Config::from_str(
+            r#"
+            devices: [/dev/input/event3]
+            clone_identity: true
+            layouts: [layout.json, { qmk: info.json, keys: keys.json }]
+            layers: [Qwerty, "", Sys]
+            panic_chord: [LEFTCTRL, ESC]
+            chatter:
+              threshold: 30
+              overrides: { SPACE: 50 }
+            controller:
+              vendor_id: 0x4b41
+              host_reports: true
+            integrations:
+              i3:
+                default_layer: 0
+                rules: [{ class: steam, layer: 5 }]
+            mice:
+              - device: /dev/input/event5
+                buttons: [BTN_SIDE, BTN_EXTRA]
+                clone_identity: true
+            software:
+              keymap: keymap.json
+              tapping_term: 175
+            metrics:
+              listen: 127.0.0.1:9102
+            stats:
+              file: stats.json
+              pause_on: [{ title: Vault }]
+            "#,
+            "yaml",
+        )
+        .unwrap();
+
+        for config in [toml, yaml] {
+            assert_eq!(config.devices, vec!["/dev/input/event3"]);
+            assert!(config.clone_identity);
+            assert_eq!(
+                config.layouts,
+                vec![
+                    LayoutFile::Layout("layout.json".into()),
+                    LayoutFile::Qmk {
+                        qmk: "info.json".into(),
+                        layout: None,
+                        keys: "keys.json".into()
+                    }
+                ]
+            );
+            assert_eq!(config.controller.vendor_id, Some(0x4b41));
+            assert_eq!(config.controller.product_id, None);
+            assert!(config.controller.host_reports);
+            assert_eq!(config.panic_chord, vec![KeyCode::LEFTCTRL, KeyCode::ESC]);
+            assert_eq!(
+                config.chatter,
+                Some(ChatterConfig {
+                    threshold: 30,
+                    overrides: HashMap::from([(KeyCode::SPACE, 50)]),
+                })
+            );
+            assert_eq!(config.layer_name(0), Some("Qwerty"));
+            assert_eq!(config.layer_name(1), None);
+            assert_eq!(config.layer_name(9), None);
+            assert_eq!(
+                config.mice,
+                vec![MouseConfig {
+                    device: "/dev/input/event5".to_string(),
+                    buttons: vec![KeyCode::BTN_SIDE, KeyCode::BTN_EXTRA],
+                    axes: vec![],
+                    clone_identity: true,
+                }]
+            );
+
+            assert_eq!(
+                config.software,
+                Some(SoftwareConfig {
+                    keymap: "keymap.json".into(),
+                    tapping_term: Some(175),
+                })
+            );
+            assert_eq!(
+                config.metrics,
+                MetricsConfig {
+                    listen: Some("127.0.0.1:9102".to_string()),
+                    textfile: None,
+                }
+            );
+            let stats = config.stats.unwrap();
+            assert_eq!(stats.file, PathBuf::from("stats.json"));
+            assert_eq!(
+                stats.pause_rules(),
+                vec![WindowMatch {
+                    class: None,
+                    title: Some("Vault".to_string())
+                }]
+            );
+
+            let i3 = config.integrations.i3.unwrap();
+            assert_eq!(i3.layer_for(Some("steam"), Some("Steam")), Some(5));
+            assert_eq!(i3.layer_for(Some("firefox"), None), Some(0));
+        }
+    }
+
+    #[test]
+    fn test_default_pause_rules() {
+        let stats = StatsConfig {
+            file: "stats.json".into(),
+            pause_on: None,
+        };
+        let pauses = |class, title| {
+            stats
+                .pause_rules()
+                .iter()
+                .any(|rule| rule.matches(Some(class), Some(title)))
+        };
+
+        assert!(pauses("Pinentry-gtk-2", "pinentry"));
+        assert!(pauses("firefox", "Password Required - Mozilla Firefox"));
+        assert!(!pauses("kitty", "vim"));
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let config = Config::from_str(
+            r#"
+            panic_chord = ["LEFTCTRL", "ESC"]
+            pipeline = [
+                { stage = "remap", keys = { CAPSLOCK = "ESC" } },
+                { stage = "filter", keys = ["INSERT"] },
+                { stage = "passthrough" },
+                { stage = "panic_chord" },
+            ]
+            "#,
+            "toml",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.stages().unwrap(),
+            vec![
+                StageConfig::Remap {
+                    keys: HashMap::from([(KeyCode::CAPSLOCK, KeyCode::ESC)])
+                },
+                StageConfig::Filter {
+                    keys: vec![KeyCode::INSERT]
+                },
+                StageConfig::Passthrough { keys: vec![] },
+                StageConfig::PanicChord,
+            ]
+        );
+
+        let defaults =
+            Config::from_str("panic_chord = [\"ESC\"]\n[chatter]\nthreshold = 30", "toml").unwrap();
+        assert_eq!(
+            defaults.stages().unwrap(),
+            vec![StageConfig::Debounce, StageConfig::PanicChord]
+        );
+
+        let missing = Config::from_str("pipeline = [{ stage = \"stats\" }]", "toml").unwrap();
+        assert!(missing.stages().is_err());
+
+        let without_chord = Config::from_str(
+            "panic_chord = [\"ESC\"]\npipeline = [{ stage = \"filter\", keys = [\"A\"] }]",
+            "toml",
+        )
+        .unwrap();
+        assert!(without_chord.stages().is_err());
+
+        let chord_after = |stage: &str| {
+            Config::from_str(
+                &format!(
+                    "panic_chord = [\"LEFTCTRL\", \"ESC\"]\npipeline = [{stage}, {{ stage = \"panic_chord\" }}]"
+                ),
+                "toml",
+            )
+            .unwrap()
+            .stages()
+        };
+        assert!(chord_after("{ stage = \"remap\", keys = { ESC = \"GRAVE\" } }").is_err());
+        assert!(chord_after("{ stage = \"filter\", keys = [\"LEFTCTRL\"] }").is_err());
+        assert!(chord_after("{ stage = \"passthrough\", keys = [\"ESC\"] }").is_err());
+        assert!(chord_after("{ stage = \"remap\", keys = { CAPSLOCK = \"ESC\" } }").is_ok());
+        assert!(chord_after("{ stage = \"filter\", keys = [\"INSERT\"] }").is_ok());
+        assert!(Config::from_str(
+            "pipeline = [{ stage = \"filter\", keys = [], key = \"A\" }]",
+            "toml"
+        )
+        .is_err());
+        assert!(Config::from_str("pipeline = [{ stage = \"macro\" }]", "toml").is_err());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        assert!(Config::from_str("device = \"/dev/input/event3\"", "toml").is_err());
+        assert!(Config::from_str("panic_chord = [\"NOT_A_KEY\"]", "toml").is_err());
+        assert!(Config::from_str("", "ini").is_err());
+    }
+}
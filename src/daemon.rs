@@ -0,0 +1,725 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use log::{error, info, warn};
+use nix::errno::Errno;
+
+use crate::config::{Config, ControllerConfig, StatsConfig};
+use crate::event_input_device::{self, EventDevice, Mouse};
+use crate::i3;
+use crate::ipc::{self, IpcRequest};
+use crate::key_event::layout_validation::{self, Severity, ValidationOptions};
+use crate::key_event::matix_mapper::{Layout, MatrixMapper};
+use crate::key_event::{KeyEvent, MyTime};
+use crate::key_macro::Macro;
+use crate::keyboard::{Operation, SharedKeyboard};
+use crate::metrics::{self, Metrics};
+use crate::pipeline::{Context, Pipeline};
+use crate::uinput::Identity;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum DaemonEvent {
+    Input(Result<KeyEvent, Errno>),
+    Reload,
+    Stop,
+}
+
+impl From<Result<KeyEvent, Errno>> for DaemonEvent {
+    fn from(value: Result<KeyEvent, Errno>) -> Self {
+        Self::Input(value)
+    }
+}
+
+/// Where the daemon reads its configuration and layout from, so they can be
+/// read again when reloading.
+pub struct Sources {
+    /// Config file given on the command line, the default one is used otherwise
+    pub config: Option<PathBuf>,
+    /// Layout file or built-in layout name given on the command line,
+    /// overriding the configured layouts
+    pub layout: Option<String>,
+}
+
+impl Sources {
+    pub fn load(&self) -> anyhow::Result<(Config, Layout)> {
+        let config = Config::load(self.config.as_deref())?;
+
+        let layout = match &self.layout {
+            Some(name) => Layout::from_name_or_file(name)?,
+            None => config.load_layouts()?,
+        };
+
+        Ok((config, layout))
+    }
+
+    fn watched_files(&self, config: &Config) -> Vec<PathBuf> {
+        // Watching every default config file notices one being created
+        let config_files = match &self.config {
+            Some(path) => vec![path.clone()],
+            None => Config::default_paths(),
+        };
+        let layouts = match &self.layout {
+            Some(name) => vec![PathBuf::from(name)],
+            None => config
+                .layouts
+                .iter()
+                .flat_map(|l| l.paths())
+                .map(PathBuf::from)
+                .collect(),
+        };
+
+        config_files.into_iter().chain(layouts).collect()
+    }
+}
+
+pub struct Daemon {
+    keyboard: Arc<Mutex<SharedKeyboard>>,
+    sources: Sources,
+    settings: Config,
+    layout: Arc<RwLock<Layout>>,
+    mapper: MatrixMapper,
+    watched: Arc<Mutex<Vec<PathBuf>>>,
+    metrics: Arc<Metrics>,
+    pipeline: Pipeline,
+    /// Taken by the passthrough keyboard whenever the pipeline is rebuilt
+    identity: Identity,
+    /// Set while a window pausing statistics has the focus, or while the
+    /// focused window is unknown
+    stats_paused: Arc<AtomicBool>,
+}
+
+impl Daemon {
+    pub fn new(
+        keyboard: SharedKeyboard,
+        sources: Sources,
+        identity: Identity,
+    ) -> anyhow::Result<Self> {
+        let (settings, layout) = sources.load()?;
+        let watched = sources.watched_files(&settings);
+        log_diagnostics(&layout, &settings);
+
+        let pausing = settings
+            .stats
+            .as_ref()
+            .is_some_and(|stats| !stats.pause_rules().is_empty());
+        let stats_paused = Arc::new(AtomicBool::new(pausing));
+        let pipeline = Pipeline::from_config(&settings, stats_paused.clone(), &identity)?;
+
+        Ok(Self {
+            pipeline,
+            identity,
+            stats_paused,
+            metrics: keyboard.metrics(),
+            keyboard: Arc::new(Mutex::new(keyboard)),
+            sources,
+            settings,
+            mapper: MatrixMapper::new(&layout),
+            layout: Arc::new(RwLock::new(layout)),
+            watched: Arc::new(Mutex::new(watched)),
+        })
+    }
+
+    fn send_message(&self, operation: Operation) -> anyhow::Result<()> {
+        self.keyboard
+            .lock()
+            .expect("keyboard lock poisoned")
+            .send_message(operation)
+            .map(|_| ())
+    }
+
+    /// Forwards events from `devices` and the selected buttons of `mice` until
+    /// one of them fails, the panic chord is pressed or the daemon is told to
+    /// stop.
+    pub fn run(mut self, devices: Vec<EventDevice>, mice: Vec<Mouse>) -> anyhow::Result<()> {
+        let (tx, rx) = channel();
+
+        // Macros are an extra, keys are forwarded without them
+        if let Err(e) = self.serve_ipc() {
+            warn!("Not listening for IPC requests: {:?}", e);
+        }
+        self.watch_integrations();
+        watch_files(self.watched.clone(), tx.clone());
+        watch_signals(tx.clone())?;
+        self.export_metrics()?;
+        event_input_device::merge_mice(mice, tx.clone(), self.metrics.clone());
+        event_input_device::merge(devices, tx, self.metrics.clone());
+
+        if self.settings.stats.is_some() {
+            // Per-layer counts start from the layer the controller is on
+            if let Err(e) = self.send_message(Operation::GetLayer) {
+                warn!("Could not read the current layer: {:?}", e);
+            }
+        }
+
+        let result = self.forward(rx);
+        self.pipeline.finish();
+
+        result
+    }
+
+    fn forward(&mut self, rx: Receiver<DaemonEvent>) -> anyhow::Result<()> {
+        loop {
+            // Events held back by the pipeline go on once their time comes
+            let event = match self.pipeline.deadline() {
+                Some(deadline) => match rx.recv_timeout(deadline.since(MyTime::default())) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        if !self.handle_deadline(MyTime::default())? {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                    event => event?,
+                },
+                None => rx.recv()?,
+            };
+
+            match event {
+                DaemonEvent::Input(event) => {
+                    if !self.handle_event(event?)? {
+                        return Ok(());
+                    }
+                }
+                DaemonEvent::Reload => {
+                    if let Err(e) = self.reload() {
+                        error!("Reload failed, keeping the current layout: {:?}", e);
+                    }
+                }
+                DaemonEvent::Stop => {
+                    return self
+                        .keyboard
+                        .lock()
+                        .expect("keyboard lock poisoned")
+                        .release_all();
+                }
+            }
+        }
+    }
+
+    /// Runs `event` through the pipeline, then sends the operations it maps to
+    /// to the controller. Returns false when the daemon should stop.
+    fn handle_event(&mut self, event: KeyEvent) -> anyhow::Result<bool> {
+        self.run_pipeline(|pipeline, context| pipeline.process(event, context))
+    }
+
+    /// Like `handle_event`, for the events the pipeline let through at `now`
+    fn handle_deadline(&mut self, now: MyTime) -> anyhow::Result<bool> {
+        self.run_pipeline(|pipeline, context| pipeline.tick(now, context))
+    }
+
+    fn run_pipeline<F>(&mut self, run: F) -> anyhow::Result<bool>
+    where
+        F: FnOnce(&mut Pipeline, &mut Context) -> anyhow::Result<Vec<Operation>>,
+    {
+        let layer = self
+            .keyboard
+            .lock()
+            .expect("keyboard lock poisoned")
+            .layer();
+        let mut context = Context {
+            mapper: &self.mapper,
+            layer,
+            stop: false,
+        };
+        let operations = run(&mut self.pipeline, &mut context)?;
+
+        if context.stop {
+            self.keyboard
+                .lock()
+                .expect("keyboard lock poisoned")
+                .release_all()?;
+            return Ok(false);
+        }
+
+        for operation in operations {
+            self.send_message(operation)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Reads the configuration and layout again. Held keys release positions
+    /// they no longer map to, so nothing stays stuck on the controller. When
+    /// the pipeline changes every held key is released, as it may have been
+    /// remapped.
+    fn reload(&mut self) -> anyhow::Result<()> {
+        let (mut settings, layout) = self.sources.load()?;
+        let mapper = MatrixMapper::new(&layout);
+        log_diagnostics(&layout, &settings);
+
+        // Statistics keep running as they were started, pausing on the windows
+        // the focus watcher was told about
+        if settings.stats != self.settings.stats {
+            warn!("Statistics settings changed, restart the daemon to apply");
+            settings.stats = self.settings.stats.clone();
+        }
+
+        let pipeline_changed = settings.stages()? != self.settings.stages()?
+            || settings.chatter != self.settings.chatter
+            || settings.panic_chord != self.settings.panic_chord;
+        if pipeline_changed {
+            // Saves the statistics the new pipeline reads back. Finishing
+            // only saves, so the current pipeline and what it holds are kept
+            // as they were when the new one cannot be built.
+            self.pipeline.finish();
+            let pipeline =
+                Pipeline::from_config(&settings, self.stats_paused.clone(), &self.identity)?;
+            let released = std::mem::replace(&mut self.pipeline, pipeline).release_all();
+
+            for operation in released {
+                self.send_message(operation)?;
+            }
+            info!("Rebuilt the pipeline");
+        }
+
+        for operation in self.pipeline.remap(&mapper) {
+            self.send_message(operation)?;
+        }
+
+        let hid = |c: &ControllerConfig| {
+            (
+                c.vendor_id,
+                c.product_id,
+                c.usage_page,
+                c.usage,
+                c.host_reports,
+            )
+        };
+        if hid(&settings.controller) != hid(&self.settings.controller) {
+            warn!("Controller settings changed, restart the daemon to apply");
+        }
+        if settings.devices != self.settings.devices || settings.mice != self.settings.mice {
+            warn!("Input devices changed, restart the daemon to apply");
+        }
+        if settings.metrics != self.settings.metrics {
+            warn!("Metrics settings changed, restart the daemon to apply");
+        }
+        if settings.software != self.settings.software {
+            warn!("Software keymap settings changed, restart the daemon to apply");
+        }
+        if settings.integrations.i3.is_some() != self.settings.integrations.i3.is_some() {
+            warn!("Integrations changed, restart the daemon to apply");
+        }
+
+        *self.watched.lock().expect("watch lock poisoned") = self.sources.watched_files(&settings);
+        *self.layout.write().expect("layout lock poisoned") = layout;
+        self.mapper = mapper;
+        self.settings = settings;
+
+        self.metrics.reload();
+        info!("Reloaded configuration and layout");
+
+        Ok(())
+    }
+
+    fn export_metrics(&self) -> anyhow::Result<()> {
+        let config = &self.settings.metrics;
+
+        if let Some(address) = &config.listen {
+            metrics::serve(address, self.metrics.clone())
+                .map_err(|e| anyhow::anyhow!("cannot serve metrics on {address}: {e}"))?;
+            info!("Serving metrics on http://{address}/metrics");
+        }
+        if let Some(path) = &config.textfile {
+            metrics::write_textfile(path, self.metrics.clone());
+            info!("Writing metrics to {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    fn serve_ipc(&self) -> anyhow::Result<()> {
+        let keyboard = self.keyboard.clone();
+        let layout = self.layout.clone();
+        let socket = ipc::socket_path()?;
+
+        info!("Listening for IPC requests on {}", socket.display());
+        ipc::serve(&socket, move |request| match request {
+            IpcRequest::RunMacro { steps } => {
+                let actions =
+                    Macro { steps }.actions(Some(&layout.read().expect("layout lock poisoned")));
+
+                actions
+                    .and_then(|actions| {
+                        Macro::play(actions, |operation| {
+                            keyboard
+                                .lock()
+                                .expect("keyboard lock poisoned")
+                                .send_message(operation)
+                                .map(|_| ())
+                        })
+                    })
+                    .into()
+            }
+        })
+    }
+
+    /// Follows the focused window to change layers and pause statistics
+    fn watch_integrations(&self) {
+        let i3_config = self.settings.integrations.i3.clone();
+        let pausing = self
+            .settings
+            .stats
+            .as_ref()
+            .map(StatsConfig::pause_rules)
+            .filter(|rules| !rules.is_empty())
+            .map(|rules| (rules, self.stats_paused.clone()));
+        if i3_config.is_none() && pausing.is_none() {
+            return;
+        }
+
+        let keyboard = self.keyboard.clone();
+        let stopped = pausing.as_ref().map(|(_, paused)| paused.clone());
+        let mut current_layer = None;
+
+        i3::spawn_focus_watcher(
+            move |window| {
+                let (class, title) = (window.class.as_deref(), window.title.as_deref());
+
+                if let Some((rules, paused)) = &pausing {
+                    let pause = rules.iter().any(|rule| rule.matches(class, title));
+                    if paused.swap(pause, Ordering::Relaxed) != pause {
+                        info!("Statistics {}", if pause { "paused" } else { "resumed" });
+                    }
+                }
+
+                let layer = i3_config
+                    .as_ref()
+                    .and_then(|config| config.layer_for(class, title));
+                if let Some(layer) = layer.filter(|l| Some(*l) != current_layer) {
+                    current_layer = Some(layer);
+                    if let Err(e) = keyboard
+                        .lock()
+                        .expect("keyboard lock poisoned")
+                        .send_message(Operation::ChangeLayer(layer))
+                    {
+                        error!("Could not change layer: {:?}", e);
+                    }
+                }
+            },
+            move || {
+                if let Some(paused) = stopped {
+                    paused.store(true, Ordering::Relaxed);
+                    warn!("Statistics paused until restart, the focused window is unknown");
+                }
+            },
+        );
+    }
+}
+
+/// Reports problems in the layout without refusing to use it
+fn log_diagnostics(layout: &Layout, settings: &Config) {
+    let options = ValidationOptions {
+        matrix_size: settings.matrix_size(),
+        device_keys: None,
+    };
+
+    for diagnostic in layout_validation::validate(layout, &options) {
+        match diagnostic.severity {
+            Severity::Error => error!("Layout: {}", diagnostic.message),
+            Severity::Warning => warn!("Layout: {}", diagnostic.message),
+        }
+    }
+}
+
+/// Modification times of the watched files, `None` for the missing ones
+#[derive(Default)]
+struct FileWatcher {
+    last_seen: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl FileWatcher {
+    /// Whether any of `paths` appeared or was modified since the last poll.
+    /// Paths polled for the first time are only recorded, they were read when
+    /// they started being watched.
+    fn poll(&mut self, paths: &[PathBuf]) -> bool {
+        let mut changed = false;
+
+        for path in paths {
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+            match self.last_seen.insert(path.clone(), modified) {
+                Some(previous) if modified.is_some() && previous != modified => {
+                    info!("{} changed", path.display());
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+
+        changed
+    }
+}
+
+/// Polls the modification time of the watched files, asking for a reload when
+/// any of them changes or appears.
+fn watch_files(watched: Arc<Mutex<Vec<PathBuf>>>, tx: Sender<DaemonEvent>) {
+    std::thread::spawn(move || {
+        let mut watcher = FileWatcher::default();
+
+        loop {
+            let paths = watched.lock().expect("watch lock poisoned").clone();
+
+            if watcher.poll(&paths) && tx.send(DaemonEvent::Reload).is_err() {
+                break;
+            }
+
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+    });
+}
+
+/// Asks for a reload on every SIGHUP, and to stop on SIGTERM and SIGINT so
+/// everything is given back as when the panic chord is pressed. The handlers
+/// are in place once this returns.
+fn watch_signals(tx: Sender<DaemonEvent>) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (mut hangup, mut terminate, mut interrupt) = {
+        let _runtime = runtime.enter();
+        (
+            signal(SignalKind::hangup())?,
+            signal(SignalKind::terminate())?,
+            signal(SignalKind::interrupt())?,
+        )
+    };
+
+    std::thread::spawn(move || {
+        runtime.block_on(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = hangup.recv() => {
+                        info!("Received SIGHUP");
+                        DaemonEvent::Reload
+                    }
+                    _ = terminate.recv() => {
+                        info!("Received SIGTERM, stopping");
+                        DaemonEvent::Stop
+                    }
+                    _ = interrupt.recv() => {
+                        info!("Received SIGINT, stopping");
+                        DaemonEvent::Stop
+                    }
+                };
+
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+
+    use crate::daemon::{Daemon, FileWatcher, Sources};
+    use crate::key_event::key_code::KeyCode;
+    use crate::key_event::KeyEvent;
+    use crate::keyboard::{Backend, KeyboardResponse, Operation, Result, SharedKeyboard};
+    use crate::test_fixtures::{at, ABC_LAYOUT};
+    use crate::uinput::Identity;
+
+    /// Records the operations instead of sending them
+    struct Recorder(Arc<Mutex<Vec<Operation>>>);
+
+    impl Backend for Recorder {
+        fn send_message(&mut self, operation: Operation) -> Result<KeyboardResponse> {
+            self.0.lock().unwrap().push(operation);
+            Ok(KeyboardResponse::None)
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("daemon-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A daemon reading `config.toml` and `layout.json` in `dir`, and the
+    /// operations it sends
+    fn daemon(dir: &Path, config: &str) -> (Daemon, Arc<Mutex<Vec<Operation>>>) {
+        std::fs::write(dir.join("config.toml"), config).unwrap();
+        std::fs::write(dir.join("layout.json"), ABC_LAYOUT).unwrap();
+
+        let sent = Arc::default();
+        let keyboard = SharedKeyboard::new(Box::new(Recorder(Arc::clone(&sent))));
+        let sources = Sources {
+            config: Some(dir.join("config.toml")),
+            layout: Some(dir.join("layout.json").to_string_lossy().into_owned()),
+        };
+
+        (
+            Daemon::new(keyboard, sources, Identity::default()).unwrap(),
+            sent,
+        )
+    }
+
+    fn sent_since(sent: &Mutex<Vec<Operation>>) -> Vec<Operation> {
+        sent.lock().unwrap().drain(..).collect()
+    }
+
+    #[test]
+    fn test_reload_releases_remapped_keys() {
+        let dir = test_dir("remap");
+        let (mut daemon, sent) = daemon(&dir, "");
+
+        daemon
+            .handle_event(KeyEvent::Press(KeyCode::A, false, at(0)))
+            .unwrap();
+        daemon
+            .handle_event(KeyEvent::Press(KeyCode::B, false, at(0)))
+            .unwrap();
+        assert_eq!(
+            sent_since(&sent),
+            vec![
+                Operation::UpdateMatrix(true, 0, 0),
+                Operation::UpdateMatrix(true, 0, 1)
+            ]
+        );
+
+        // A moves to where C was, B stays
+        let moved = ABC_LAYOUT
+            .replace(r#""label": "A""#, r#""label": "X""#)
+            .replace(r#""label": "C""#, r#""label": "A""#);
+        std::fs::write(dir.join("layout.json"), moved).unwrap();
+        daemon.reload().unwrap();
+        assert_eq!(
+            sent_since(&sent),
+            vec![Operation::UpdateMatrix(false, 0, 0)]
+        );
+
+        daemon
+            .handle_event(KeyEvent::Release(KeyCode::A, at(10)))
+            .unwrap();
+        daemon
+            .handle_event(KeyEvent::Press(KeyCode::A, false, at(20)))
+            .unwrap();
+        assert_eq!(sent_since(&sent), vec![Operation::UpdateMatrix(true, 0, 2)]);
+
+        // A new pipeline may remap anything, so every held key is released
+        std::fs::write(
+            dir.join("config.toml"),
+            r#"panic_chord = ["LEFTCTRL", "ESC"]"#,
+        )
+        .unwrap();
+        daemon.reload().unwrap();
+        let released = sent_since(&sent);
+        assert_eq!(released.len(), 2);
+        assert!(released.contains(&Operation::UpdateMatrix(false, 0, 1)));
+        assert!(released.contains(&Operation::UpdateMatrix(false, 0, 2)));
+
+        std::fs::write(dir.join("config.toml"), "devices = [").unwrap();
+        assert!(daemon.reload().is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_pipeline_rebuild_keeps_held_keys() {
+        let dir = test_dir("rebuild");
+        let stats = format!("[stats]\nfile = {:?}\npause_on = []\n", dir.join("stats"));
+        let (mut daemon, sent) = daemon(&dir, &stats);
+
+        daemon
+            .handle_event(KeyEvent::Press(KeyCode::A, false, at(0)))
+            .unwrap();
+        assert_eq!(sent_since(&sent), vec![Operation::UpdateMatrix(true, 0, 0)]);
+
+        // The statistics file can no longer be read back
+        std::fs::create_dir(dir.join("stats")).unwrap();
+        std::fs::write(
+            dir.join("config.toml"),
+            format!("panic_chord = [\"LEFTCTRL\", \"ESC\"]\n{stats}"),
+        )
+        .unwrap();
+        assert!(daemon.reload().is_err());
+        assert_eq!(sent_since(&sent), vec![]);
+
+        daemon
+            .handle_event(KeyEvent::Release(KeyCode::A, at(10)))
+            .unwrap();
+        assert_eq!(
+            sent_since(&sent),
+            vec![Operation::UpdateMatrix(false, 0, 0)]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_held_back_events() {
+        let dir = test_dir("chatter");
+        let (mut daemon, sent) = daemon(&dir, "[chatter]\nthreshold = 30");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        daemon
+            .handle_event(KeyEvent::Press(KeyCode::A, false, at(0)))
+            .unwrap();
+        assert_eq!(daemon.pipeline.deadline(), Some(at(30)));
+        assert_eq!(sent_since(&sent), vec![]);
+
+        daemon.handle_deadline(at(30)).unwrap();
+        assert_eq!(daemon.pipeline.deadline(), None);
+        assert_eq!(sent_since(&sent), vec![Operation::UpdateMatrix(true, 0, 0)]);
+    }
+
+    #[test]
+    fn test_key_held_by_several_devices() {
+        let dir = test_dir("shared");
+        let (mut daemon, sent) = daemon(&dir, "");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for event in [
+            KeyEvent::Press(KeyCode::A, false, at(0)),
+            KeyEvent::Press(KeyCode::A, false, at(10)),
+            KeyEvent::Release(KeyCode::A, at(20)),
+        ] {
+            daemon.handle_event(event).unwrap();
+        }
+        assert_eq!(sent_since(&sent), vec![Operation::UpdateMatrix(true, 0, 0)]);
+
+        daemon
+            .handle_event(KeyEvent::Release(KeyCode::A, at(30)))
+            .unwrap();
+        assert_eq!(
+            sent_since(&sent),
+            vec![Operation::UpdateMatrix(false, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_file_watcher() {
+        let dir = test_dir("watch");
+        let path = dir.join("layout.json");
+        let paths = [path.clone()];
+        let mut watcher = FileWatcher::default();
+
+        assert!(!watcher.poll(&paths));
+        std::fs::write(&path, ABC_LAYOUT).unwrap();
+        assert!(watcher.poll(&paths));
+        assert!(!watcher.poll(&paths));
+
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(watcher.poll(&paths));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,11 +2,19 @@ use std::fs::File;
 use std::mem::{size_of, transmute};
 use std::os::fd::AsRawFd;
 
+use log::error;
 use nix::errno::Errno;
-use nix::libc::input_event;
+use nix::libc::{input_event, input_id};
 use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
 
-use crate::key_event::{IOctlOp, KeyEvent, EVIOCGRAB};
+use crate::config::MouseConfig;
+use crate::key_event::key_code::{KeyCode, KEY_MAX};
+use crate::key_event::relative_axis::RelativeAxis;
+use crate::key_event::{eviocgbit, eviocgname, IOctlOp, KeyEvent, EVIOCGID, EVIOCGRAB, EV_KEY};
+use crate::metrics::Metrics;
+use crate::uinput::{UinputBuilder, UinputDevice, CLONE_SUFFIX};
 
 pub struct EventDevice(std::fs::File);
 impl Deref for EventDevice {
@@ -47,20 +55,181 @@ impl EventDevice {
         Ok(Self::new(f)?)
     }
 
+    /// Opens the device without grabbing it, so its events still reach everyone else
+    pub fn from_path_shared<S: AsRef<str>>(path: S) -> Result<Self, anyhow::Error> {
+        let f = std::fs::OpenOptions::new().read(true).open(path.as_ref())?;
+
+        Ok(EventDevice(f))
+    }
+
+    pub fn name(&self) -> Result<String, std::io::Error> {
+        let mut name = [0u8; 256];
+
+        unsafe {
+            let r = nix::libc::ioctl(self.as_raw_fd(), eviocgname(name.len()), name.as_mut_ptr());
+            if r < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        Ok(String::from_utf8_lossy(&name[..len]).into_owned())
+    }
+
+    /// Path the device was opened at, with symlinks such as
+    /// `/dev/input/by-id/…` resolved, so it tells identical devices apart
+    pub fn path(&self) -> Result<std::path::PathBuf, std::io::Error> {
+        std::fs::read_link(format!("/proc/self/fd/{}", self.as_raw_fd()))
+    }
+
+    /// Bus type, vendor, product and version of the device
+    pub fn id(&self) -> Result<input_id, std::io::Error> {
+        let mut id = input_id {
+            bustype: 0,
+            vendor: 0,
+            product: 0,
+            version: 0,
+        };
+
+        unsafe {
+            let r = nix::libc::ioctl(self.as_raw_fd(), EVIOCGID, &mut id);
+            if r < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(id)
+    }
+
+    /// Codes of `event_type`, up to `max`, the device reports being able to
+    /// send. Event type 0 lists the event types themselves.
+    pub fn supported_codes(&self, event_type: u16, max: u16) -> Result<Vec<u16>, std::io::Error> {
+        let mut bits = vec![0u8; max as usize / 8 + 1];
+
+        unsafe {
+            let r = nix::libc::ioctl(
+                self.as_raw_fd(),
+                eviocgbit(event_type, bits.len()),
+                bits.as_mut_ptr(),
+            );
+            if r < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok((0..=max)
+            .filter(|code| bits[*code as usize / 8] & (1 << (code % 8)) != 0)
+            .collect())
+    }
+
+    /// Keys the device reports being able to send
+    pub fn supported_keys(&self) -> Result<Vec<KeyCode>, std::io::Error> {
+        Ok(self
+            .supported_codes(EV_KEY, KEY_MAX)?
+            .into_iter()
+            .filter_map(KeyCode::from_repr)
+            .collect())
+    }
+
     pub fn next(&self) -> Result<KeyEvent, Errno> {
         let mut buf = [0u8; size_of::<nix::libc::input_event>()];
 
         nix::unistd::read(self.as_raw_fd(), &mut buf)?;
 
         let input_event: input_event = unsafe { transmute(buf) };
-        let key_event: KeyEvent = input_event.into();
-
-        let debug_ev: input_event = key_event.into();
-        // println!("----------------");
-        // println!("{:?}", key_event);
-        // println!("{:?}", input_event);
-        // println!("{:?}", debug_ev);
 
         Ok(input_event.into())
     }
 }
+
+/// Reads every device on its own thread, sending their events to `tx`. A
+/// thread stops after its device fails to read, after sending the error.
+pub fn merge<T>(devices: Vec<EventDevice>, tx: Sender<T>, metrics: Arc<Metrics>)
+where
+    T: From<Result<KeyEvent, Errno>> + Send + 'static,
+{
+    for device in devices {
+        let tx = tx.clone();
+        let metrics = metrics.clone();
+        let path = device.path().unwrap_or_default();
+        let path = path.to_string_lossy().into_owned();
+        std::thread::spawn(move || loop {
+            let event = device.next();
+            metrics.input_event(&path);
+            let failed = event.is_err();
+
+            if tx.send(event.into()).is_err() || failed {
+                break;
+            }
+        });
+    }
+}
+
+/// A grabbed mouse whose `buttons` go to the controller. Everything else it
+/// sends is written to a virtual mouse, so it keeps moving and clicking.
+pub struct Mouse {
+    device: EventDevice,
+    buttons: Vec<KeyCode>,
+    axes: Vec<RelativeAxis>,
+    passthrough: UinputDevice,
+}
+
+impl Mouse {
+    pub fn open(config: &MouseConfig) -> Result<Self, anyhow::Error> {
+        let device = EventDevice::from_path(&config.device)?;
+        let passthrough = match config.clone_identity {
+            true => UinputBuilder::clone_of(&device, CLONE_SUFFIX)?,
+            false => UinputBuilder::new("qmk-virtual-keyboard mouse passthrough")
+                .capabilities_of(&device)?,
+        };
+
+        Ok(Self {
+            device,
+            buttons: config.buttons.clone(),
+            axes: config.axes.clone(),
+            passthrough: passthrough.build()?,
+        })
+    }
+}
+
+/// Reads every mouse on its own thread like `merge`, sending only presses and
+/// releases of their selected buttons and steps of their selected axes to
+/// `tx`. The high resolution events of a selected wheel are dropped.
+pub fn merge_mice<T>(mice: Vec<Mouse>, tx: Sender<T>, metrics: Arc<Metrics>)
+where
+    T: From<Result<KeyEvent, Errno>> + Send + 'static,
+{
+    for mut mouse in mice {
+        let tx = tx.clone();
+        let metrics = metrics.clone();
+        let path = mouse.device.path().unwrap_or_default();
+        let path = path.to_string_lossy().into_owned();
+        std::thread::spawn(move || loop {
+            let event = mouse.device.next();
+            metrics.input_event(&path);
+
+            let forward = match &event {
+                Ok(KeyEvent::Press(code, ..) | KeyEvent::Release(code, ..))
+                    if mouse.buttons.contains(code) =>
+                {
+                    true
+                }
+                Ok(KeyEvent::Relative(axis, ..)) if mouse.axes.contains(&axis.low_res()) => {
+                    axis.low_res() == *axis
+                }
+                Ok(event) => {
+                    if let Err(e) = mouse.passthrough.send_event_only(*event) {
+                        error!("Could not pass mouse event through: {:?}", e);
+                    }
+                    false
+                }
+                Err(_) => true,
+            };
+            let failed = event.is_err();
+
+            if forward && tx.send(event.into()).is_err() || failed {
+                break;
+            }
+        });
+    }
+}
@@ -0,0 +1,415 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, error, info};
+
+use crate::key_event::key_code::{KeyCode, MODIFIERS};
+use crate::key_event::KeyEvent;
+use crate::keyboard::{HidInfo, Keyboard, Operation, HOST_REPORT_ID};
+use crate::uinput::{Identity, UinputDevice};
+
+/// Wait before opening the controller again after losing it, doubled after
+/// each failed attempt up to `MAX_RECONNECT_DELAY`
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Linux keys of the HID keyboard usages, as the kernel's HID driver maps them
+const USAGES: [(u8, KeyCode); 135] = [
+    (0x04, KeyCode::A),
+    (0x05, KeyCode::B),
+    (0x06, KeyCode::C),
+    (0x07, KeyCode::D),
+    (0x08, KeyCode::E),
+    (0x09, KeyCode::F),
+    (0x0a, KeyCode::G),
+    (0x0b, KeyCode::H),
+    (0x0c, KeyCode::I),
+    (0x0d, KeyCode::J),
+    (0x0e, KeyCode::K),
+    (0x0f, KeyCode::L),
+    (0x10, KeyCode::M),
+    (0x11, KeyCode::N),
+    (0x12, KeyCode::O),
+    (0x13, KeyCode::P),
+    (0x14, KeyCode::Q),
+    (0x15, KeyCode::R),
+    (0x16, KeyCode::S),
+    (0x17, KeyCode::T),
+    (0x18, KeyCode::U),
+    (0x19, KeyCode::V),
+    (0x1a, KeyCode::W),
+    (0x1b, KeyCode::X),
+    (0x1c, KeyCode::Y),
+    (0x1d, KeyCode::Z),
+    (0x1e, KeyCode::KEY_1),
+    (0x1f, KeyCode::KEY_2),
+    (0x20, KeyCode::KEY_3),
+    (0x21, KeyCode::KEY_4),
+    (0x22, KeyCode::KEY_5),
+    (0x23, KeyCode::KEY_6),
+    (0x24, KeyCode::KEY_7),
+    (0x25, KeyCode::KEY_8),
+    (0x26, KeyCode::KEY_9),
+    (0x27, KeyCode::KEY_0),
+    (0x28, KeyCode::ENTER),
+    (0x29, KeyCode::ESC),
+    (0x2a, KeyCode::BACKSPACE),
+    (0x2b, KeyCode::TAB),
+    (0x2c, KeyCode::SPACE),
+    (0x2d, KeyCode::MINUS),
+    (0x2e, KeyCode::EQUAL),
+    (0x2f, KeyCode::LEFTBRACE),
+    (0x30, KeyCode::RIGHTBRACE),
+    (0x31, KeyCode::BACKSLASH),
+    (0x32, KeyCode::BACKSLASH),
+    (0x33, KeyCode::SEMICOLON),
+    (0x34, KeyCode::APOSTROPHE),
+    (0x35, KeyCode::GRAVE),
+    (0x36, KeyCode::COMMA),
+    (0x37, KeyCode::DOT),
+    (0x38, KeyCode::SLASH),
+    (0x39, KeyCode::CAPSLOCK),
+    (0x3a, KeyCode::F1),
+    (0x3b, KeyCode::F2),
+    (0x3c, KeyCode::F3),
+    (0x3d, KeyCode::F4),
+    (0x3e, KeyCode::F5),
+    (0x3f, KeyCode::F6),
+    (0x40, KeyCode::F7),
+    (0x41, KeyCode::F8),
+    (0x42, KeyCode::F9),
+    (0x43, KeyCode::F10),
+    (0x44, KeyCode::F11),
+    (0x45, KeyCode::F12),
+    (0x46, KeyCode::SYSRQ),
+    (0x47, KeyCode::SCROLLLOCK),
+    (0x48, KeyCode::PAUSE),
+    (0x49, KeyCode::INSERT),
+    (0x4a, KeyCode::HOME),
+    (0x4b, KeyCode::PAGEUP),
+    (0x4c, KeyCode::DELETE),
+    (0x4d, KeyCode::END),
+    (0x4e, KeyCode::PAGEDOWN),
+    (0x4f, KeyCode::RIGHT),
+    (0x50, KeyCode::LEFT),
+    (0x51, KeyCode::DOWN),
+    (0x52, KeyCode::UP),
+    (0x53, KeyCode::NUMLOCK),
+    (0x54, KeyCode::KPSLASH),
+    (0x55, KeyCode::KPASTERISK),
+    (0x56, KeyCode::KPMINUS),
+    (0x57, KeyCode::KPPLUS),
+    (0x58, KeyCode::KPENTER),
+    (0x59, KeyCode::KP1),
+    (0x5a, KeyCode::KP2),
+    (0x5b, KeyCode::KP3),
+    (0x5c, KeyCode::KP4),
+    (0x5d, KeyCode::KP5),
+    (0x5e, KeyCode::KP6),
+    (0x5f, KeyCode::KP7),
+    (0x60, KeyCode::KP8),
+    (0x61, KeyCode::KP9),
+    (0x62, KeyCode::KP0),
+    (0x63, KeyCode::KPDOT),
+    (0x64, KeyCode::KEY_102ND),
+    (0x65, KeyCode::COMPOSE),
+    (0x66, KeyCode::POWER),
+    (0x67, KeyCode::KPEQUAL),
+    (0x68, KeyCode::F13),
+    (0x69, KeyCode::F14),
+    (0x6a, KeyCode::F15),
+    (0x6b, KeyCode::F16),
+    (0x6c, KeyCode::F17),
+    (0x6d, KeyCode::F18),
+    (0x6e, KeyCode::F19),
+    (0x6f, KeyCode::F20),
+    (0x70, KeyCode::F21),
+    (0x71, KeyCode::F22),
+    (0x72, KeyCode::F23),
+    (0x73, KeyCode::F24),
+    (0x74, KeyCode::OPEN),
+    (0x75, KeyCode::HELP),
+    (0x76, KeyCode::PROPS),
+    (0x77, KeyCode::FRONT),
+    (0x78, KeyCode::STOP),
+    (0x79, KeyCode::AGAIN),
+    (0x7a, KeyCode::UNDO),
+    (0x7b, KeyCode::CUT),
+    (0x7c, KeyCode::COPY),
+    (0x7d, KeyCode::PASTE),
+    (0x7e, KeyCode::FIND),
+    (0x7f, KeyCode::MUTE),
+    (0x80, KeyCode::VOLUMEUP),
+    (0x81, KeyCode::VOLUMEDOWN),
+    (0x85, KeyCode::KPCOMMA),
+    (0x87, KeyCode::RO),
+    (0x88, KeyCode::KATAKANAHIRAGANA),
+    (0x89, KeyCode::YEN),
+    (0x8a, KeyCode::HENKAN),
+    (0x8b, KeyCode::MUHENKAN),
+    (0x8c, KeyCode::KPJPCOMMA),
+    (0x90, KeyCode::HANGEUL),
+    (0x91, KeyCode::HANJA),
+];
+
+/// HID usage reported in every key slot when too many keys are held
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// A boot protocol keyboard report sent back by the controller:
+/// `[0x48, modifiers, reserved, key1, …, key6]`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HidReport {
+    pub modifiers: u8,
+    pub keys: [u8; 6],
+}
+
+impl HidReport {
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        match buffer {
+            [HOST_REPORT_ID, modifiers, _, keys @ ..] if keys.len() >= 6 => Some(Self {
+                modifiers: *modifiers,
+                keys: keys[..6].try_into().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Keys held according to this report, modifiers first
+    fn pressed(&self) -> Vec<KeyCode> {
+        let modifiers = MODIFIERS
+            .into_iter()
+            .enumerate()
+            .filter(|(bit, _)| self.modifiers & 1 << bit != 0)
+            .map(|(_, code)| code);
+        let keys = self.keys.iter().filter_map(|usage| {
+            USAGES
+                .iter()
+                .find(|(u, _)| u == usage)
+                .map(|(_, code)| *code)
+        });
+
+        modifiers.chain(keys).collect()
+    }
+}
+
+/// Turns the successive reports of the controller into key presses and
+/// releases
+#[derive(Default)]
+pub struct ReportDecoder {
+    pressed: Vec<KeyCode>,
+}
+
+impl ReportDecoder {
+    /// Returns the keys to press (true) and release (false), releases first
+    pub fn update(&mut self, report: &HidReport) -> Vec<(KeyCode, bool)> {
+        if report.keys.contains(&ERROR_ROLL_OVER) {
+            return Vec::new();
+        }
+
+        let pressed = report.pressed();
+        let released = self.pressed.iter().filter(|code| !pressed.contains(code));
+        let new = pressed.iter().filter(|code| !self.pressed.contains(code));
+
+        let events = released
+            .map(|code| (*code, false))
+            .chain(new.map(|code| (*code, true)))
+            .collect();
+        self.pressed = pressed;
+
+        events
+    }
+}
+
+/// Has the controller send its keyboard reports back to the host, which types
+/// them through a uinput keyboard. When the controller goes away, for example
+/// when it is plugged again, it is opened again and host reports are turned
+/// back on. The controller goes back to acting as a USB keyboard once this is
+/// dropped.
+pub struct HostReports {
+    /// Handle host reports are turned on and off through, replaced when the
+    /// controller is opened again
+    control: Arc<Mutex<Keyboard>>,
+    /// Set once dropped, so host reports are not turned back on
+    stopped: Arc<AtomicBool>,
+}
+
+impl HostReports {
+    pub fn start(hid_info: &HidInfo, identity: &Identity) -> anyhow::Result<Self> {
+        let control = Keyboard::new(hid_info)?;
+        control.send_message(Operation::HostReports(true))?;
+        // Gives the controller its keyboard back if anything below fails
+        let host_reports = Self {
+            control: Arc::new(Mutex::new(control)),
+            stopped: Arc::default(),
+        };
+
+        let reader = Keyboard::new(hid_info)?;
+        let device = identity
+            .keyboard("qmk-virtual-keyboard")
+            .keys(USAGES.map(|(_, code)| code))
+            .keys(MODIFIERS)
+            .build()?;
+
+        let reopen = Reopen {
+            hid_info: hid_info.clone(),
+            control: host_reports.control.clone(),
+            stopped: host_reports.stopped.clone(),
+        };
+        std::thread::spawn(move || read_reports(reader, device, reopen));
+
+        Ok(host_reports)
+    }
+}
+
+impl Drop for HostReports {
+    fn drop(&mut self) {
+        let control = self.control.lock().expect("control lock poisoned");
+        self.stopped.store(true, Ordering::Relaxed);
+
+        if let Err(e) = control.send_message(Operation::HostReports(false)) {
+            error!("Could not give the controller its keyboard back: {:?}", e);
+        }
+    }
+}
+
+/// Types the reports `reader` receives on `device`, opening the controller
+/// again whenever reading fails, until host reports stop
+fn read_reports(mut reader: Keyboard, mut device: UinputDevice, reopen: Reopen) {
+    let mut decoder = ReportDecoder::default();
+
+    loop {
+        let report = match reader.read_report() {
+            Ok(buffer) => match HidReport::parse(&buffer) {
+                Some(report) => report,
+                None => continue,
+            },
+            Err(e) => {
+                error!("Lost the controller's keyboard reports: {:?}", e);
+                // Nothing stays held while the controller is away
+                type_keys(&mut device, decoder.update(&HidReport::default()));
+
+                match reopen.reader() {
+                    Some(reopened) => reader = reopened,
+                    None => return,
+                }
+                continue;
+            }
+        };
+
+        type_keys(&mut device, decoder.update(&report));
+    }
+}
+
+fn type_keys(device: &mut UinputDevice, keys: Vec<(KeyCode, bool)>) {
+    for (code, pressed) in keys {
+        let event = match pressed {
+            true => KeyEvent::Press(code, false, Default::default()),
+            false => KeyEvent::Release(code, Default::default()),
+        };
+        if let Err(e) = device.send_event(event) {
+            error!("Could not type {:?}: {:?}", code, e);
+        }
+    }
+}
+
+/// What the reader thread needs to open the controller again
+struct Reopen {
+    hid_info: HidInfo,
+    control: Arc<Mutex<Keyboard>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Reopen {
+    /// Opens the controller again and turns host reports back on, retrying
+    /// with a growing delay. Returns a new reader, or `None` once host reports
+    /// stopped.
+    fn reader(&self) -> Option<Keyboard> {
+        let mut delay = RECONNECT_DELAY;
+
+        loop {
+            std::thread::sleep(delay);
+            if self.stopped.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            match self.attempt() {
+                Ok(Some(reader)) => {
+                    info!("Reading the controller's keyboard reports again");
+                    return Some(reader);
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    debug!("Could not open the controller again: {:?}", e);
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
+    }
+
+    fn attempt(&self) -> anyhow::Result<Option<Keyboard>> {
+        let reader = Keyboard::new(&self.hid_info)?;
+        let control = Keyboard::new(&self.hid_info)?;
+
+        // Dropping `HostReports` turns them off under this lock
+        let mut current = self.control.lock().expect("control lock poisoned");
+        if self.stopped.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        control.send_message(Operation::HostReports(true))?;
+        *current = control;
+
+        Ok(Some(reader))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::host_reports::{HidReport, ReportDecoder};
+    use crate::key_event::key_code::KeyCode;
+
+    fn report(modifiers: u8, keys: &[u8]) -> HidReport {
+        let mut buffer = vec![0x48, modifiers, 0];
+        buffer.extend(keys);
+        buffer.resize(32, 0);
+
+        HidReport::parse(&buffer).unwrap()
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut decoder = ReportDecoder::default();
+
+        assert_eq!(
+            decoder.update(&report(0x02, &[0x04])),
+            vec![(KeyCode::LEFTSHIFT, true), (KeyCode::A, true)]
+        );
+        assert_eq!(
+            decoder.update(&report(0x02, &[0x04, 0x1e])),
+            vec![(KeyCode::KEY_1, true)]
+        );
+        // Too many keys, nothing changes
+        assert_eq!(decoder.update(&report(0x02, &[0x01; 6])), vec![]);
+        assert_eq!(
+            decoder.update(&report(0x40, &[0x00, 0x1e])),
+            vec![
+                (KeyCode::LEFTSHIFT, false),
+                (KeyCode::A, false),
+                (KeyCode::RIGHTALT, true)
+            ]
+        );
+        assert_eq!(
+            decoder.update(&report(0x00, &[])),
+            vec![(KeyCode::RIGHTALT, false), (KeyCode::KEY_1, false)]
+        );
+
+        // Losing the controller releases everything
+        decoder.update(&report(0x01, &[0x04]));
+        assert_eq!(
+            decoder.update(&HidReport::default()),
+            vec![(KeyCode::LEFTCTRL, false), (KeyCode::A, false)]
+        );
+
+        assert_eq!(HidReport::parse(&[0x45, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+}
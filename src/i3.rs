@@ -0,0 +1,88 @@
+use futures::StreamExt;
+use tokio_i3ipc::event::{Event, Subscribe, WindowChange};
+use tokio_i3ipc::reply::Node;
+use tokio_i3ipc::I3;
+
+#[derive(Debug, Clone, Default)]
+pub struct FocusedWindow {
+    pub class: Option<String>,
+    pub title: Option<String>,
+}
+
+impl From<Node> for FocusedWindow {
+    fn from(node: Node) -> Self {
+        let (class, title) = node
+            .window_properties
+            .map(|p| (p.class, p.title))
+            .unwrap_or_default();
+
+        Self {
+            class,
+            title: title.or(node.name),
+        }
+    }
+}
+
+/// The focused node of the tree under `node`
+fn focused(node: Node) -> Option<Node> {
+    if node.focused {
+        return Some(node);
+    }
+
+    node.nodes
+        .into_iter()
+        .chain(node.floating_nodes)
+        .find_map(focused)
+}
+
+/// Calls `on_focus` with the window focused at first, then every time the
+/// focused window changes, or the focused window changes its title.
+pub async fn watch_focus<F>(mut on_focus: F) -> std::io::Result<()>
+where
+    F: FnMut(FocusedWindow),
+{
+    let mut i3 = I3::connect().await?;
+    if let Some(node) = focused(i3.get_tree().await?) {
+        on_focus(node.into());
+    }
+    i3.subscribe([Subscribe::Window]).await?;
+
+    let mut events = i3.listen();
+
+    while let Some(event) = events.next().await {
+        if let Event::Window(window) = event? {
+            let is_focus = match window.change {
+                WindowChange::Focus => true,
+                WindowChange::Title => window.container.focused,
+                _ => false,
+            };
+
+            if is_focus {
+                on_focus(window.container.into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `watch_focus` on a dedicated thread, so it can be used from the
+/// blocking daemon loop. `on_stop` is called once windows are no longer
+/// watched.
+pub fn spawn_focus_watcher<F, S>(on_focus: F, on_stop: S) -> std::thread::JoinHandle<()>
+where
+    F: FnMut(FocusedWindow) + Send + 'static,
+    S: FnOnce() + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Could not start i3 runtime");
+
+        if let Err(e) = runtime.block_on(watch_focus(on_focus)) {
+            log::error!("i3 integration stopped: {:?}", e);
+        }
+        on_stop();
+    })
+}
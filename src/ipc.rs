@@ -0,0 +1,219 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::key_macro::MacroStep;
+
+/// Used for the socket when `XDG_RUNTIME_DIR` is not set, as for a daemon
+/// started by a system service manager
+const FALLBACK_DIR: &str = "/run/qmk-virtual-keyboard";
+
+/// Requests accepted by the `virtual-keyboard` daemon. Each request is a single
+/// line of JSON on the daemon socket, answered by a single line of JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum IpcRequest {
+    /// The macro itself rather than a path, the daemon never reads files on
+    /// behalf of clients
+    RunMacro { steps: Vec<MacroStep> },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum IpcResponse {
+    Ok,
+    Error { message: String },
+}
+
+impl From<anyhow::Result<()>> for IpcResponse {
+    fn from(value: anyhow::Result<()>) -> Self {
+        match value {
+            Ok(()) => Self::Ok,
+            Err(e) => Self::Error {
+                message: format!("{:?}", e),
+            },
+        }
+    }
+}
+
+fn euid() -> u32 {
+    unsafe { nix::libc::geteuid() }
+}
+
+/// The socket lives in `XDG_RUNTIME_DIR`, or in a directory of its own under
+/// `/run` without one. Either must only be accessible by the current user.
+pub fn socket_path() -> anyhow::Result<PathBuf> {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let dir = PathBuf::from(FALLBACK_DIR);
+            if !dir.exists() {
+                std::fs::DirBuilder::new()
+                    .mode(0o700)
+                    .create(&dir)
+                    .map_err(|e| {
+                        anyhow::anyhow!(
+                            "XDG_RUNTIME_DIR is not set and {} cannot be created: {e}",
+                            dir.display()
+                        )
+                    })?;
+            }
+            dir
+        }
+    };
+
+    let metadata = std::fs::metadata(&dir)?;
+    if metadata.uid() != euid() || metadata.mode() & 0o077 != 0 {
+        bail!(
+            "{} must be owned by the current user and not accessible by anyone else",
+            dir.display()
+        );
+    }
+
+    Ok(dir.join("qmk-virtual-keyboard.sock"))
+}
+
+/// Removes a socket left behind by a daemon that did not stop cleanly. Anything
+/// else at `path`, or a socket a daemon still listens on, is left alone.
+fn remove_stale_socket(path: &Path) -> anyhow::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    if !metadata.file_type().is_socket() || metadata.uid() != euid() {
+        bail!(
+            "refusing to replace {}, it is not a socket of this user",
+            path.display()
+        );
+    }
+    if UnixStream::connect(path).is_ok() {
+        bail!("another daemon is already listening on {}", path.display());
+    }
+
+    warn!("Removing stale socket {}", path.display());
+    Ok(std::fs::remove_file(path)?)
+}
+
+/// The user id of the process on the other end of `stream`
+fn peer_uid(stream: &UnixStream) -> anyhow::Result<u32> {
+    let mut credentials = nix::libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<nix::libc::ucred>() as nix::libc::socklen_t;
+
+    let r = unsafe {
+        nix::libc::getsockopt(
+            stream.as_raw_fd(),
+            nix::libc::SOL_SOCKET,
+            nix::libc::SO_PEERCRED,
+            &mut credentials as *mut _ as *mut nix::libc::c_void,
+            &mut len,
+        )
+    };
+    if r < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    Ok(credentials.uid)
+}
+
+/// Listens on `path` in a background thread, handling each connection in its
+/// own thread so a long running macro doesn't block other clients. Only
+/// clients running as the same user as the daemon are answered.
+pub fn serve<F>(path: &Path, handler: F) -> anyhow::Result<()>
+where
+    F: Fn(IpcRequest) -> IpcResponse + Send + Sync + 'static,
+{
+    remove_stale_socket(path)?;
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    let handler = std::sync::Arc::new(handler);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let handler = handler.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = handle_client(stream, handler.as_ref()) {
+                            error!("IPC client error: {:?}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("IPC accept error: {:?}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_client<F>(stream: UnixStream, handler: &F) -> anyhow::Result<()>
+where
+    F: Fn(IpcRequest) -> IpcResponse,
+{
+    let uid = peer_uid(&stream)?;
+    if uid != euid() {
+        bail!("rejected client running as uid {uid}");
+    }
+
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        debug!("IPC request: {}", line);
+
+        let response = match serde_json::from_str::<IpcRequest>(&line) {
+            Ok(request) => handler(request),
+            Err(e) => IpcResponse::Error {
+                message: e.to_string(),
+            },
+        };
+
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+pub fn request(path: &Path, request: &IpcRequest) -> anyhow::Result<IpcResponse> {
+    let mut stream = UnixStream::connect(path)?;
+
+    serde_json::to_writer(&mut stream, request)?;
+    stream.write_all(b"\n")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ipc::{serve, IpcResponse};
+
+    #[test]
+    fn test_refuses_to_replace_other_files() {
+        let path = std::env::temp_dir().join(format!("ipc-test-{}", std::process::id()));
+        std::fs::write(&path, "not a socket").unwrap();
+
+        let result = serve(&path, |_| IpcResponse::Ok);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(contents, "not a socket");
+    }
+}
@@ -0,0 +1,175 @@
+use crate::key_event::matix_mapper::{Layout, LayoutItem, MatrixPosition};
+
+/*
+ * Built-in layouts are described row by row. Each row is a list of segments,
+ * each a space separated list of evdev key names, optionally followed by
+ * `:width` or `:widthxheight` in key units, and of `+width` gaps. Keys are numbered left to right,
+ * so a key's matrix position is its row and its index in that row.
+ */
+
+const FUNCTION_ROW: &str = "ESC +1 F1 F2 F3 F4 +0.5 F5 F6 F7 F8 +0.5 F9 F10 F11 F12";
+
+const ANSI: [&str; 5] = [
+    "GRAVE 1 2 3 4 5 6 7 8 9 0 MINUS EQUAL BACKSPACE:2",
+    "TAB:1.5 Q W E R T Y U I O P LEFTBRACE RIGHTBRACE BACKSLASH:1.5",
+    "CAPSLOCK:1.75 A S D F G H J K L SEMICOLON APOSTROPHE ENTER:2.25",
+    "LEFTSHIFT:2.25 Z X C V B N M COMMA DOT SLASH RIGHTSHIFT:2.75",
+    "LEFTCTRL:1.25 LEFTMETA:1.25 LEFTALT:1.25 SPACE:6.25 RIGHTALT:1.25 RIGHTMETA:1.25 COMPOSE:1.25 RIGHTCTRL:1.25",
+];
+
+/// The ISO enter spans two rows, it is numbered in the upper one and drawn
+/// as its narrower lower part. Evdev calls the key left of it BACKSLASH.
+const ISO: [&str; 5] = [
+    ANSI[0],
+    "TAB:1.5 Q W E R T Y U I O P LEFTBRACE RIGHTBRACE +0.25 ENTER:1.25x2",
+    "CAPSLOCK:1.75 A S D F G H J K L SEMICOLON APOSTROPHE BACKSLASH +1.25",
+    "LEFTSHIFT:1.25 102ND Z X C V B N M COMMA DOT SLASH RIGHTSHIFT:2.75",
+    ANSI[4],
+];
+
+const NAVIGATION: [&str; 6] = [
+    "+0.25 SYSRQ SCROLLLOCK PAUSE",
+    "+0.25 INSERT HOME PAGEUP",
+    "+0.25 DELETE END PAGEDOWN",
+    "+3.25",
+    "+1.25 UP +1",
+    "+0.25 LEFT DOWN RIGHT",
+];
+
+const NUMPAD: [&str; 6] = [
+    "",
+    "+0.25 NUMLOCK KPSLASH KPASTERISK KPMINUS",
+    "+0.25 KP7 KP8 KP9 KPPLUS:1x2",
+    "+0.25 KP4 KP5 KP6",
+    "+0.25 KP1 KP2 KP3 KPENTER:1x2",
+    "+0.25 KP0:2 KPDOT",
+];
+
+const LAPTOP: [&str; 6] = [
+    "ESC F1 F2 F3 F4 F5 F6 F7 F8 F9 F10 F11 F12 INSERT DELETE",
+    ANSI[0],
+    ANSI[1],
+    ANSI[2],
+    "LEFTSHIFT:2.25 Z X C V B N M COMMA DOT SLASH RIGHTSHIFT:1.75 UP +1",
+    "LEFTCTRL:1.25 LEFTMETA:1.25 LEFTALT:1.25 SPACE:5.75 RIGHTALT:1.25 RIGHTCTRL:1.25 LEFT DOWN RIGHT",
+];
+
+/// Names of the built-in layouts, in the order they are listed to the user
+pub const NAMES: [&str; 5] = ["ansi104", "iso105", "tkl", "60%", "laptop"];
+
+/// Layout used when none is given or configured
+pub const DEFAULT: &str = "ansi104";
+
+/// Row segments of the built-in layout with this name
+fn rows(name: &str) -> Option<Vec<Vec<&'static str>>> {
+    let full_size = |main: [&'static str; 5], numpad: bool| {
+        std::iter::once(FUNCTION_ROW)
+            .chain(main)
+            .zip(NAVIGATION)
+            .zip(NUMPAD)
+            .map(|((main, navigation), numpad_row)| match numpad {
+                true => vec![main, navigation, numpad_row],
+                false => vec![main, navigation],
+            })
+            .collect()
+    };
+
+    match name.to_ascii_lowercase().as_str() {
+        "ansi104" => Some(full_size(ANSI, true)),
+        "iso105" => Some(full_size(ISO, true)),
+        "tkl" => Some(full_size(ANSI, false)),
+        "60%" => Some(ANSI.iter().map(|row| vec![*row]).collect()),
+        "laptop" => Some(LAPTOP.iter().map(|row| vec![*row]).collect()),
+        _ => None,
+    }
+}
+
+/// Returns the built-in layout with this name, ignoring case
+pub fn builtin(name: &str) -> Option<Layout> {
+    let rows = rows(name)?;
+    let mut layout = Vec::new();
+
+    for (row, segments) in rows.iter().enumerate() {
+        let mut x = 0.0;
+        let mut col = 0;
+
+        for token in segments
+            .iter()
+            .flat_map(|segment| segment.split_whitespace())
+        {
+            if let Some(gap) = token.strip_prefix('+') {
+                x += gap.parse::<f32>().expect("invalid gap in built-in layout");
+                continue;
+            }
+
+            let (label, size) = token.split_once(':').unwrap_or((token, "1"));
+            let (width, height) = size.split_once('x').unwrap_or((size, "1"));
+            let width: f32 = width.parse().expect("invalid width in built-in layout");
+
+            layout.push(LayoutItem {
+                matrix: MatrixPosition::from((row as u8, col)),
+                x,
+                y: row as f32,
+                w: width,
+                h: height.parse().expect("invalid height in built-in layout"),
+                label: label.to_string(),
+                ..Default::default()
+            });
+
+            x += width;
+            col += 1;
+        }
+    }
+
+    Some(Layout {
+        layout,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_event::builtin_layouts::{builtin, NAMES};
+    use crate::key_event::layout_validation::{validate, ValidationOptions};
+    use crate::key_event::matix_mapper::MatrixPosition;
+
+    #[test]
+    fn test_builtin_layouts() {
+        let sizes: Vec<usize> = NAMES
+            .iter()
+            .map(|name| builtin(name).unwrap().layout.len())
+            .collect();
+        assert_eq!(sizes, vec![104, 105, 87, 61, 78]);
+
+        for name in NAMES {
+            let layout = builtin(name).unwrap();
+            let options = ValidationOptions {
+                matrix_size: Some((6, 21)),
+                device_keys: None,
+            };
+            assert!(
+                validate(&layout, &options).is_empty(),
+                "{name} is not valid"
+            );
+        }
+
+        let ansi = builtin("ANSI104").unwrap();
+        assert_eq!(
+            ansi.position_of("ESC"),
+            Some(MatrixPosition { row: 0, col: 0 })
+        );
+        assert_eq!(
+            ansi.position_of("TAB"),
+            Some(MatrixPosition { row: 2, col: 0 })
+        );
+        assert_eq!(
+            ansi.position_of("F2"),
+            Some(MatrixPosition { row: 0, col: 2 })
+        );
+        assert_eq!(
+            ansi.position_of("KPENTER"),
+            Some(MatrixPosition { row: 4, col: 16 })
+        );
+        assert!(builtin("unknown").is_none());
+    }
+}
@@ -1,4 +1,19 @@
 use strum_macros::{AsRefStr, EnumString, FromRepr};
+
+/// Highest key or button code evdev can send
+pub const KEY_MAX: u16 = 0x2ff;
+
+/// Modifier keys, in the bit order of a HID report's modifier byte
+pub const MODIFIERS: [KeyCode; 8] = [
+    KeyCode::LEFTCTRL,
+    KeyCode::LEFTSHIFT,
+    KeyCode::LEFTALT,
+    KeyCode::LEFTMETA,
+    KeyCode::RIGHTCTRL,
+    KeyCode::RIGHTSHIFT,
+    KeyCode::RIGHTALT,
+    KeyCode::RIGHTMETA,
+];
 /*
  * Keys and buttons
  *
@@ -12,7 +27,7 @@ use strum_macros::{AsRefStr, EnumString, FromRepr};
 
 #[allow(clippy::upper_case_acronyms)]
 #[allow(non_camel_case_types)]
-#[derive(FromRepr, AsRefStr, EnumString, Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(FromRepr, AsRefStr, EnumString, Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[strum(ascii_case_insensitive)]
 #[repr(u16)]
 pub enum KeyCode {
@@ -303,6 +318,422 @@ pub enum KeyCode {
     RFKILL = 247, /* Key that controls all radios */
 
     MICMUTE = 248, /* Mute / unmute the microphone */
+
+    /* Buttons */
+    // BTN_MISC = BTN_0,
+    BTN_0 = 256,
+    BTN_1 = 257,
+    BTN_2 = 258,
+    BTN_3 = 259,
+    BTN_4 = 260,
+    BTN_5 = 261,
+    BTN_6 = 262,
+    BTN_7 = 263,
+    BTN_8 = 264,
+    BTN_9 = 265,
+
+    // BTN_MOUSE = BTN_LEFT,
+    BTN_LEFT = 272,
+    BTN_RIGHT = 273,
+    BTN_MIDDLE = 274,
+    BTN_SIDE = 275,
+    BTN_EXTRA = 276,
+    BTN_FORWARD = 277,
+    BTN_BACK = 278,
+    BTN_TASK = 279,
+
+    // BTN_JOYSTICK = BTN_TRIGGER,
+    BTN_TRIGGER = 288,
+    BTN_THUMB = 289,
+    BTN_THUMB2 = 290,
+    BTN_TOP = 291,
+    BTN_TOP2 = 292,
+    BTN_PINKIE = 293,
+    BTN_BASE = 294,
+    BTN_BASE2 = 295,
+    BTN_BASE3 = 296,
+    BTN_BASE4 = 297,
+    BTN_BASE5 = 298,
+    BTN_BASE6 = 299,
+
+    BTN_DEAD = 303,
+    // BTN_GAMEPAD = BTN_SOUTH,
+    BTN_SOUTH = 304,
+    // BTN_A = BTN_SOUTH,
+    BTN_EAST = 305,
+    // BTN_B = BTN_EAST,
+    BTN_C = 306,
+    BTN_NORTH = 307,
+    // BTN_X = BTN_NORTH,
+    BTN_WEST = 308,
+    // BTN_Y = BTN_WEST,
+    BTN_Z = 309,
+    BTN_TL = 310,
+    BTN_TR = 311,
+    BTN_TL2 = 312,
+    BTN_TR2 = 313,
+    BTN_SELECT = 314,
+    BTN_START = 315,
+    BTN_MODE = 316,
+    BTN_THUMBL = 317,
+    BTN_THUMBR = 318,
+
+    // BTN_DIGI = BTN_TOOL_PEN,
+    BTN_TOOL_PEN = 320,
+    BTN_TOOL_RUBBER = 321,
+    BTN_TOOL_BRUSH = 322,
+    BTN_TOOL_PENCIL = 323,
+    BTN_TOOL_AIRBRUSH = 324,
+    BTN_TOOL_FINGER = 325,
+    BTN_TOOL_MOUSE = 326,
+    BTN_TOOL_LENS = 327,
+    BTN_TOOL_QUINTTAP = 328,
+    BTN_STYLUS3 = 329,
+    BTN_TOUCH = 330,
+    BTN_STYLUS = 331,
+    BTN_STYLUS2 = 332,
+    BTN_TOOL_DOUBLETAP = 333,
+    BTN_TOOL_TRIPLETAP = 334,
+    BTN_TOOL_QUADTAP = 335,
+    // BTN_WHEEL = BTN_GEAR_DOWN,
+    BTN_GEAR_DOWN = 336,
+    BTN_GEAR_UP = 337,
+
+    OK = 352,
+    SELECT = 353,
+    GOTO = 354,
+    CLEAR = 355,
+    POWER2 = 356,
+    OPTION = 357,
+    INFO = 358,
+    TIME = 359,
+    VENDOR = 360,
+    ARCHIVE = 361,
+    PROGRAM = 362,
+    CHANNEL = 363,
+    FAVORITES = 364,
+    EPG = 365,
+    PVR = 366,
+    MHP = 367,
+    LANGUAGE = 368,
+    TITLE = 369,
+    SUBTITLE = 370,
+    ANGLE = 371,
+    FULL_SCREEN = 372,
+    // KEY_ZOOM = KEY_FULL_SCREEN,
+    MODE = 373,
+    KEYBOARD = 374,
+    ASPECT_RATIO = 375,
+    // KEY_SCREEN = KEY_ASPECT_RATIO,
+    PC = 376,
+    TV = 377,
+    TV2 = 378,
+    VCR = 379,
+    VCR2 = 380,
+    SAT = 381,
+    SAT2 = 382,
+    CD = 383,
+    TAPE = 384,
+    RADIO = 385,
+    TUNER = 386,
+    PLAYER = 387,
+    TEXT = 388,
+    DVD = 389,
+    AUX = 390,
+    MP3 = 391,
+    AUDIO = 392,
+    VIDEO = 393,
+    DIRECTORY = 394,
+    LIST = 395,
+    MEMO = 396,
+    CALENDAR = 397,
+    RED = 398,
+    GREEN = 399,
+    YELLOW = 400,
+    BLUE = 401,
+    CHANNELUP = 402,
+    CHANNELDOWN = 403,
+    FIRST = 404,
+    LAST = 405,
+    AB = 406,
+    NEXT = 407,
+    RESTART = 408,
+    SLOW = 409,
+    SHUFFLE = 410,
+    BREAK = 411,
+    PREVIOUS = 412,
+    DIGITS = 413,
+    TEEN = 414,
+    TWEN = 415,
+    VIDEOPHONE = 416,
+    GAMES = 417,
+    ZOOMIN = 418,
+    ZOOMOUT = 419,
+    ZOOMRESET = 420,
+    WORDPROCESSOR = 421,
+    EDITOR = 422,
+    SPREADSHEET = 423,
+    GRAPHICSEDITOR = 424,
+    PRESENTATION = 425,
+    DATABASE = 426,
+    NEWS = 427,
+    VOICEMAIL = 428,
+    ADDRESSBOOK = 429,
+    MESSENGER = 430,
+    DISPLAYTOGGLE = 431,
+    // KEY_BRIGHTNESS_TOGGLE = KEY_DISPLAYTOGGLE,
+    SPELLCHECK = 432,
+    LOGOFF = 433,
+    DOLLAR = 434,
+    EURO = 435,
+    FRAMEBACK = 436,
+    FRAMEFORWARD = 437,
+    CONTEXT_MENU = 438,
+    MEDIA_REPEAT = 439,
+    #[strum(serialize = "10CHANNELSUP")]
+    #[serde(rename = "10CHANNELSUP")]
+    KEY_10CHANNELSUP = 440,
+    #[strum(serialize = "10CHANNELSDOWN")]
+    #[serde(rename = "10CHANNELSDOWN")]
+    KEY_10CHANNELSDOWN = 441,
+    IMAGES = 442,
+
+    NOTIFICATION_CENTER = 444,
+    PICKUP_PHONE = 445,
+    HANGUP_PHONE = 446,
+    LINK_PHONE = 447,
+    DEL_EOL = 448,
+    DEL_EOS = 449,
+    INS_LINE = 450,
+    DEL_LINE = 451,
+
+    FN = 464,
+    FN_ESC = 465,
+    FN_F1 = 466,
+    FN_F2 = 467,
+    FN_F3 = 468,
+    FN_F4 = 469,
+    FN_F5 = 470,
+    FN_F6 = 471,
+    FN_F7 = 472,
+    FN_F8 = 473,
+    FN_F9 = 474,
+    FN_F10 = 475,
+    FN_F11 = 476,
+    FN_F12 = 477,
+    FN_1 = 478,
+    FN_2 = 479,
+    FN_D = 480,
+    FN_E = 481,
+    FN_F = 482,
+    FN_S = 483,
+    FN_B = 484,
+    FN_RIGHT_SHIFT = 485,
+
+    BRL_DOT1 = 497,
+    BRL_DOT2 = 498,
+    BRL_DOT3 = 499,
+    BRL_DOT4 = 500,
+    BRL_DOT5 = 501,
+    BRL_DOT6 = 502,
+    BRL_DOT7 = 503,
+    BRL_DOT8 = 504,
+    BRL_DOT9 = 505,
+    BRL_DOT10 = 506,
+
+    NUMERIC_0 = 512,
+    NUMERIC_1 = 513,
+    NUMERIC_2 = 514,
+    NUMERIC_3 = 515,
+    NUMERIC_4 = 516,
+    NUMERIC_5 = 517,
+    NUMERIC_6 = 518,
+    NUMERIC_7 = 519,
+    NUMERIC_8 = 520,
+    NUMERIC_9 = 521,
+    NUMERIC_STAR = 522,
+    NUMERIC_POUND = 523,
+    NUMERIC_A = 524,
+    NUMERIC_B = 525,
+    NUMERIC_C = 526,
+    NUMERIC_D = 527,
+    CAMERA_FOCUS = 528,
+    WPS_BUTTON = 529,
+    TOUCHPAD_TOGGLE = 530,
+    TOUCHPAD_ON = 531,
+    TOUCHPAD_OFF = 532,
+    CAMERA_ZOOMIN = 533,
+    CAMERA_ZOOMOUT = 534,
+    CAMERA_UP = 535,
+    CAMERA_DOWN = 536,
+    CAMERA_LEFT = 537,
+    CAMERA_RIGHT = 538,
+    ATTENDANT_ON = 539,
+    ATTENDANT_OFF = 540,
+    ATTENDANT_TOGGLE = 541,
+    LIGHTS_TOGGLE = 542,
+
+    BTN_DPAD_UP = 544,
+    BTN_DPAD_DOWN = 545,
+    BTN_DPAD_LEFT = 546,
+    BTN_DPAD_RIGHT = 547,
+
+    ALS_TOGGLE = 560,
+    ROTATE_LOCK_TOGGLE = 561,
+    REFRESH_RATE_TOGGLE = 562,
+
+    BUTTONCONFIG = 576,
+    TASKMANAGER = 577,
+    JOURNAL = 578,
+    CONTROLPANEL = 579,
+    APPSELECT = 580,
+    SCREENSAVER = 581,
+    VOICECOMMAND = 582,
+    ASSISTANT = 583,
+    KBD_LAYOUT_NEXT = 584,
+    EMOJI_PICKER = 585,
+    DICTATE = 586,
+
+    BRIGHTNESS_MIN = 592,
+    BRIGHTNESS_MAX = 593,
+
+    KBDINPUTASSIST_PREV = 608,
+    KBDINPUTASSIST_NEXT = 609,
+    KBDINPUTASSIST_PREVGROUP = 610,
+    KBDINPUTASSIST_NEXTGROUP = 611,
+    KBDINPUTASSIST_ACCEPT = 612,
+    KBDINPUTASSIST_CANCEL = 613,
+    RIGHT_UP = 614,
+    RIGHT_DOWN = 615,
+    LEFT_UP = 616,
+    LEFT_DOWN = 617,
+    ROOT_MENU = 618,
+    MEDIA_TOP_MENU = 619,
+    NUMERIC_11 = 620,
+    NUMERIC_12 = 621,
+    AUDIO_DESC = 622,
+    #[strum(serialize = "3D_MODE")]
+    #[serde(rename = "3D_MODE")]
+    KEY_3D_MODE = 623,
+    NEXT_FAVORITE = 624,
+    STOP_RECORD = 625,
+    PAUSE_RECORD = 626,
+    VOD = 627,
+    UNMUTE = 628,
+    FASTREVERSE = 629,
+    SLOWREVERSE = 630,
+    DATA = 631,
+    ONSCREEN_KEYBOARD = 632,
+    PRIVACY_SCREEN_TOGGLE = 633,
+    SELECTIVE_SCREENSHOT = 634,
+    NEXT_ELEMENT = 635,
+    PREVIOUS_ELEMENT = 636,
+    AUTOPILOT_ENGAGE_TOGGLE = 637,
+    MARK_WAYPOINT = 638,
+    SOS = 639,
+    NAV_CHART = 640,
+    FISHING_CHART = 641,
+    SINGLE_RANGE_RADAR = 642,
+    DUAL_RANGE_RADAR = 643,
+    RADAR_OVERLAY = 644,
+    TRADITIONAL_SONAR = 645,
+    CLEARVU_SONAR = 646,
+    SIDEVU_SONAR = 647,
+    NAV_INFO = 648,
+    BRIGHTNESS_MENU = 649,
+
+    MACRO1 = 656,
+    MACRO2 = 657,
+    MACRO3 = 658,
+    MACRO4 = 659,
+    MACRO5 = 660,
+    MACRO6 = 661,
+    MACRO7 = 662,
+    MACRO8 = 663,
+    MACRO9 = 664,
+    MACRO10 = 665,
+    MACRO11 = 666,
+    MACRO12 = 667,
+    MACRO13 = 668,
+    MACRO14 = 669,
+    MACRO15 = 670,
+    MACRO16 = 671,
+    MACRO17 = 672,
+    MACRO18 = 673,
+    MACRO19 = 674,
+    MACRO20 = 675,
+    MACRO21 = 676,
+    MACRO22 = 677,
+    MACRO23 = 678,
+    MACRO24 = 679,
+    MACRO25 = 680,
+    MACRO26 = 681,
+    MACRO27 = 682,
+    MACRO28 = 683,
+    MACRO29 = 684,
+    MACRO30 = 685,
+
+    MACRO_RECORD_START = 688,
+    MACRO_RECORD_STOP = 689,
+    MACRO_PRESET_CYCLE = 690,
+    MACRO_PRESET1 = 691,
+    MACRO_PRESET2 = 692,
+    MACRO_PRESET3 = 693,
+
+    KBD_LCD_MENU1 = 696,
+    KBD_LCD_MENU2 = 697,
+    KBD_LCD_MENU3 = 698,
+    KBD_LCD_MENU4 = 699,
+    KBD_LCD_MENU5 = 700,
+
+    // BTN_TRIGGER_HAPPY = BTN_TRIGGER_HAPPY1,
+    BTN_TRIGGER_HAPPY1 = 704,
+    BTN_TRIGGER_HAPPY2 = 705,
+    BTN_TRIGGER_HAPPY3 = 706,
+    BTN_TRIGGER_HAPPY4 = 707,
+    BTN_TRIGGER_HAPPY5 = 708,
+    BTN_TRIGGER_HAPPY6 = 709,
+    BTN_TRIGGER_HAPPY7 = 710,
+    BTN_TRIGGER_HAPPY8 = 711,
+    BTN_TRIGGER_HAPPY9 = 712,
+    BTN_TRIGGER_HAPPY10 = 713,
+    BTN_TRIGGER_HAPPY11 = 714,
+    BTN_TRIGGER_HAPPY12 = 715,
+    BTN_TRIGGER_HAPPY13 = 716,
+    BTN_TRIGGER_HAPPY14 = 717,
+    BTN_TRIGGER_HAPPY15 = 718,
+    BTN_TRIGGER_HAPPY16 = 719,
+    BTN_TRIGGER_HAPPY17 = 720,
+    BTN_TRIGGER_HAPPY18 = 721,
+    BTN_TRIGGER_HAPPY19 = 722,
+    BTN_TRIGGER_HAPPY20 = 723,
+    BTN_TRIGGER_HAPPY21 = 724,
+    BTN_TRIGGER_HAPPY22 = 725,
+    BTN_TRIGGER_HAPPY23 = 726,
+    BTN_TRIGGER_HAPPY24 = 727,
+    BTN_TRIGGER_HAPPY25 = 728,
+    BTN_TRIGGER_HAPPY26 = 729,
+    BTN_TRIGGER_HAPPY27 = 730,
+    BTN_TRIGGER_HAPPY28 = 731,
+    BTN_TRIGGER_HAPPY29 = 732,
+    BTN_TRIGGER_HAPPY30 = 733,
+    BTN_TRIGGER_HAPPY31 = 734,
+    BTN_TRIGGER_HAPPY32 = 735,
+    BTN_TRIGGER_HAPPY33 = 736,
+    BTN_TRIGGER_HAPPY34 = 737,
+    BTN_TRIGGER_HAPPY35 = 738,
+    BTN_TRIGGER_HAPPY36 = 739,
+    BTN_TRIGGER_HAPPY37 = 740,
+    BTN_TRIGGER_HAPPY38 = 741,
+    BTN_TRIGGER_HAPPY39 = 742,
+    BTN_TRIGGER_HAPPY40 = 743,
+}
+
+impl KeyCode {
+    /// Every known keycode, in numeric order
+    pub fn all() -> impl Iterator<Item = KeyCode> {
+        (0..=KEY_MAX).filter_map(KeyCode::from_repr)
+    }
 }
 
 impl From<KeyCode> for usize {
@@ -317,12 +748,6 @@ impl From<KeyCode> for u16 {
     }
 }
 
-impl From<KeyCode> for u8 {
-    fn from(value: KeyCode) -> u8 {
-        value as u8
-    }
-}
-
 #[cfg(test)]
 mod test {
     use crate::key_event::key_code::KeyCode;
@@ -334,6 +759,17 @@ mod test {
         assert_eq!(KeyCode::A, "a".parse::<KeyCode>().unwrap());
         assert_eq!(KeyCode::DOCUMENTS, "DoCumEntS".parse::<KeyCode>().unwrap());
         assert_eq!(KeyCode::F12, "f12".parse::<KeyCode>().unwrap());
+        assert_eq!(KeyCode::BTN_SIDE, "btn_side".parse::<KeyCode>().unwrap());
+        assert_eq!(KeyCode::KEY_3D_MODE, "3D_MODE".parse::<KeyCode>().unwrap());
+    }
+
+    #[test]
+    fn test_high_codes() {
+        assert_eq!(KeyCode::from_repr(0x1d0), Some(KeyCode::FN));
+        assert_eq!(KeyCode::from_repr(0x290), Some(KeyCode::MACRO1));
+        assert_eq!(KeyCode::from_repr(0x2ff), None);
+        assert_eq!(KeyCode::all().last(), Some(KeyCode::BTN_TRIGGER_HAPPY40));
+        assert_eq!(u16::from(KeyCode::BRIGHTNESS_MENU), 0x289);
     }
 
     #[test]
@@ -0,0 +1,304 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+use log::warn;
+use serde_json::Value;
+
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::matix_mapper::{Layout, LayoutItem, MatrixPosition};
+
+/// Where each legend of a key ends up, by the key's alignment flags. KLE
+/// compacts the legends of a key into a single string, skipping the
+/// positions that alignment makes impossible. Position 0 is top left.
+const LABEL_MAP: [[i8; 12]; 8] = [
+    [0, 6, 2, 8, 9, 11, 3, 5, 1, 4, 7, 10],
+    [1, 7, -1, -1, 9, 11, 4, -1, -1, -1, -1, 10],
+    [3, -1, 5, -1, 9, 11, -1, -1, 4, -1, -1, 10],
+    [4, -1, -1, -1, 9, 11, -1, -1, -1, -1, -1, 10],
+    [0, 6, 2, 8, 10, -1, 3, 5, 1, 4, 7, -1],
+    [1, 7, -1, -1, 10, -1, 4, -1, -1, -1, -1, -1],
+    [3, -1, 5, -1, 10, -1, -1, -1, 4, -1, -1, -1],
+    [4, -1, -1, -1, 10, -1, -1, -1, -1, -1, -1, -1],
+];
+
+const DEFAULT_ALIGN: usize = 4;
+
+/// A key read from keyboard-layout-editor.com raw data
+#[derive(Debug, Clone)]
+pub struct KleKey {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    /// Rotation in degrees around (`rx`, `ry`)
+    pub r: f32,
+    pub rx: f32,
+    pub ry: f32,
+    /// Legends by position, 0 is top left and 11 is front right
+    pub legends: [Option<String>; 12],
+    pub decal: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+    r: f32,
+    cluster_x: f32,
+    cluster_y: f32,
+    align: usize,
+    decal: bool,
+}
+
+fn number(props: &serde_json::Map<String, Value>, key: &str) -> Option<f32> {
+    props.get(key).and_then(Value::as_f64).map(|n| n as f32)
+}
+
+/// Parses KLE raw JSON, as downloaded from keyboard-layout-editor.com
+pub fn parse(contents: &str) -> anyhow::Result<Vec<KleKey>> {
+    let rows = match serde_json::from_str(contents)? {
+        Value::Array(rows) => rows,
+        _ => bail!("KLE data must be a list of rows"),
+    };
+
+    let mut keys = Vec::new();
+    let mut state = State {
+        x: 0.0,
+        y: 0.0,
+        w: 1.0,
+        h: 1.0,
+        r: 0.0,
+        cluster_x: 0.0,
+        cluster_y: 0.0,
+        align: DEFAULT_ALIGN,
+        decal: false,
+    };
+
+    for (r, row) in rows.iter().enumerate() {
+        let row = match row {
+            Value::Array(row) => row,
+            // Keyboard metadata, only allowed as the first element
+            Value::Object(_) if r == 0 => continue,
+            _ => bail!("row {r} is not a list of keys"),
+        };
+
+        for item in row {
+            match item {
+                Value::Object(props) => {
+                    if let Some(r) = number(props, "r") {
+                        state.r = r;
+                    }
+                    if let Some(rx) = number(props, "rx") {
+                        state.cluster_x = rx;
+                        state.x = state.cluster_x;
+                        state.y = state.cluster_y;
+                    }
+                    if let Some(ry) = number(props, "ry") {
+                        state.cluster_y = ry;
+                        state.x = state.cluster_x;
+                        state.y = state.cluster_y;
+                    }
+                    if let Some(a) = props.get("a").and_then(Value::as_u64) {
+                        state.align = (a as usize).min(LABEL_MAP.len() - 1);
+                    }
+                    if let Some(d) = props.get("d").and_then(Value::as_bool) {
+                        state.decal = d;
+                    }
+                    state.x += number(props, "x").unwrap_or(0.0);
+                    state.y += number(props, "y").unwrap_or(0.0);
+                    state.w = number(props, "w").unwrap_or(state.w);
+                    state.h = number(props, "h").unwrap_or(state.h);
+                }
+                Value::String(legend) => {
+                    let mut legends: [Option<String>; 12] = Default::default();
+                    for (i, text) in legend.split('\n').enumerate().take(12) {
+                        let position = LABEL_MAP[state.align][i];
+                        if position >= 0 && !text.is_empty() {
+                            legends[position as usize] = Some(text.to_string());
+                        }
+                    }
+
+                    keys.push(KleKey {
+                        x: state.x,
+                        y: state.y,
+                        w: state.w,
+                        h: state.h,
+                        r: state.r,
+                        rx: state.cluster_x,
+                        ry: state.cluster_y,
+                        legends,
+                        decal: state.decal,
+                    });
+
+                    state.x += state.w;
+                    state.w = 1.0;
+                    state.h = 1.0;
+                    state.decal = false;
+                }
+                _ => bail!("unexpected item in row {r}: {item}"),
+            }
+        }
+
+        state.y += 1.0;
+        state.x = state.cluster_x;
+    }
+
+    Ok(keys)
+}
+
+fn parse_matrix(legend: &str) -> Option<MatrixPosition> {
+    let (row, col) = legend.split_once(',')?;
+
+    Some(MatrixPosition {
+        row: row.trim().parse().ok()?,
+        col: col.trim().parse().ok()?,
+    })
+}
+
+impl KleKey {
+    /// The `"row,col"` legend, preferring the top left one as VIA does
+    pub fn matrix(&self) -> Option<(usize, MatrixPosition)> {
+        self.legends
+            .iter()
+            .enumerate()
+            .find_map(|(i, legend)| Some(i).zip(legend.as_deref().and_then(parse_matrix)))
+    }
+
+    /// The first legend other than the matrix position, preferring legends
+    /// that name a `KeyCode`
+    pub fn label(&self) -> Option<&str> {
+        let matrix_slot = self.matrix().map(|(i, _)| i);
+        let candidates = || {
+            self.legends
+                .iter()
+                .enumerate()
+                .filter(move |(i, _)| Some(*i) != matrix_slot)
+                .filter_map(|(_, legend)| legend.as_deref())
+        };
+
+        let found = candidates().find(|legend| legend.parse::<KeyCode>().is_ok());
+        found.or_else(|| candidates().next())
+    }
+}
+
+pub fn to_layout(keys: &[KleKey]) -> anyhow::Result<Layout> {
+    let mut layout = Vec::new();
+
+    for key in keys.iter().filter(|key| !key.decal) {
+        let Some((_, matrix)) = key.matrix() else {
+            warn!(
+                "Skipping key at ({}, {}) without a row,col legend",
+                key.x, key.y
+            );
+            continue;
+        };
+
+        let label = key
+            .label()
+            .ok_or_else(|| anyhow!("key {},{} has no label", matrix.row, matrix.col))?;
+
+        layout.push(LayoutItem {
+            matrix,
+            x: key.x,
+            y: key.y,
+            w: key.w,
+            h: key.h,
+            r: key.r,
+            rx: key.rx,
+            ry: key.ry,
+            label: label.to_string(),
+            ..Default::default()
+        });
+    }
+
+    Ok(Layout {
+        layout,
+        ..Default::default()
+    })
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Layout> {
+    let contents = std::fs::read_to_string(path)?;
+    to_layout(&parse(&contents)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_event::kle;
+
+    #[test]
+    fn test_parse() {
+        let keys = kle::parse(
+            r#"[
+                { "name": "test" },
+                ["0,0\nEsc", { "x": 1 }, "0,1\nF1", { "d": true }, "logo"],
+                [{ "w": 1.5 }, "1,0\n\n\nTab", "1,1\nQ"],
+                [{ "a": 7 }, "Space"]
+            ]"#,
+        )
+        .unwrap();
+
+        let positions: Vec<_> = keys.iter().map(|k| (k.x, k.y)).collect();
+        assert_eq!(
+            positions,
+            vec![
+                (0.0, 0.0),
+                (2.0, 0.0),
+                (3.0, 0.0),
+                (0.0, 1.0),
+                (1.5, 1.0),
+                (0.0, 2.0)
+            ]
+        );
+
+        // With the default alignment, the fourth legend goes to the bottom right
+        assert_eq!(keys[3].legends[8].as_deref(), Some("Tab"));
+        // Fully centered keys put their first legend in the center
+        assert_eq!(keys[5].legends[4].as_deref(), Some("Space"));
+
+        let layout = kle::to_layout(&keys).unwrap();
+        let items: Vec<_> = layout
+            .layout
+            .iter()
+            .map(|i| (i.label.as_str(), i.matrix.row, i.matrix.col))
+            .collect();
+
+        assert_eq!(
+            items,
+            vec![("Esc", 0, 0), ("F1", 0, 1), ("Tab", 1, 0), ("Q", 1, 1)]
+        );
+        assert_eq!((layout.layout[2].x, layout.layout[2].w), (0.0, 1.5));
+        assert_eq!((layout.layout[3].x, layout.layout[3].w), (1.5, 1.0));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let keys = kle::parse(
+            r#"[
+                [{ "r": 15, "rx": 6, "ry": 3, "y": -1, "h": 2 }, "3,0", "3,1"]
+            ]"#,
+        )
+        .unwrap();
+
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|k| (k.x, k.y, k.h, k.r, k.rx, k.ry))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (6.0, 2.0, 2.0, 15.0, 6.0, 3.0),
+                (7.0, 2.0, 1.0, 15.0, 6.0, 3.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(kle::parse(r#"{ "name": "test" }"#).is_err());
+        assert!(kle::parse(r#"[["0,0"], { "name": "test" }]"#).is_err());
+        assert!(kle::to_layout(&kle::parse(r#"[["0,0"]]"#).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::{HashMap, HashSet};
+
+use strum_macros::EnumString;
+
+use crate::key_event::matix_mapper::{Layout, LayoutItem, MatrixPosition};
+
+/// Characters per key unit, including one border
+const UNIT_WIDTH: f32 = 7.0;
+
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
+const REVERSE: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+/// Black on 256-color backgrounds from blue to red, for keys from the least
+/// to the most pressed
+const HEAT: [&str; 6] = [
+    "\x1b[30;48;5;33m",
+    "\x1b[30;48;5;44m",
+    "\x1b[30;48;5;77m",
+    "\x1b[30;48;5;184m",
+    "\x1b[30;48;5;208m",
+    "\x1b[30;48;5;196m",
+];
+
+/// What is written inside each key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum Labels {
+    Keys,
+    Matrix,
+    Both,
+}
+
+impl Labels {
+    fn lines(&self, item: &LayoutItem) -> Vec<String> {
+        let matrix = format!("{},{}", item.matrix.row, item.matrix.col);
+        match self {
+            Self::Keys => vec![item.label.clone()],
+            Self::Matrix => vec![matrix],
+            Self::Both => vec![item.label.clone(), matrix],
+        }
+    }
+
+    fn line_count(&self) -> usize {
+        match self {
+            Self::Keys | Self::Matrix => 1,
+            Self::Both => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    /// Which directions box-drawing lines leave this cell to
+    edges: u8,
+    text: Option<char>,
+    style: Option<&'static str>,
+}
+
+struct Canvas(Vec<Vec<Cell>>);
+
+impl Canvas {
+    fn cell(&mut self, row: usize, col: usize) -> &mut Cell {
+        if self.0.len() <= row {
+            self.0.resize(row + 1, Vec::new());
+        }
+        let line = &mut self.0[row];
+        if line.len() <= col {
+            line.resize(col + 1, Cell::default());
+        }
+        &mut line[col]
+    }
+
+    fn horizontal(&mut self, row: usize, from: usize, to: usize) {
+        for col in from..=to {
+            let cell = self.cell(row, col);
+            if col > from {
+                cell.edges |= LEFT;
+            }
+            if col < to {
+                cell.edges |= RIGHT;
+            }
+        }
+    }
+
+    fn vertical(&mut self, col: usize, from: usize, to: usize) {
+        for row in from..=to {
+            let cell = self.cell(row, col);
+            if row > from {
+                cell.edges |= UP;
+            }
+            if row < to {
+                cell.edges |= DOWN;
+            }
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        for line in &self.0 {
+            let mut style = None;
+            for cell in line {
+                if cell.style != style {
+                    out.push_str(cell.style.unwrap_or(RESET));
+                    style = cell.style;
+                }
+                out.push(cell.text.unwrap_or_else(|| box_char(cell.edges)));
+            }
+            if style.is_some() {
+                out.push_str(RESET);
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+fn box_char(edges: u8) -> char {
+    match edges {
+        0 => ' ',
+        UP => '╵',
+        DOWN => '╷',
+        LEFT => '╴',
+        RIGHT => '╶',
+        e if e == UP | DOWN => '│',
+        e if e == LEFT | RIGHT => '─',
+        e if e == DOWN | RIGHT => '┌',
+        e if e == DOWN | LEFT => '┐',
+        e if e == UP | RIGHT => '└',
+        e if e == UP | LEFT => '┘',
+        e if e == UP | DOWN | RIGHT => '├',
+        e if e == UP | DOWN | LEFT => '┤',
+        e if e == DOWN | LEFT | RIGHT => '┬',
+        e if e == UP | LEFT | RIGHT => '┴',
+        _ => '┼',
+    }
+}
+
+/// Shortens a label to `width` characters, first dropping the LEFT and RIGHT
+/// of modifier names
+fn fit(label: &str, width: usize) -> String {
+    let label = match label.len() > width {
+        true => label.replacen("LEFT", "L", 1).replacen("RIGHT", "R", 1),
+        false => label.to_string(),
+    };
+
+    label.chars().take(width).collect()
+}
+
+/// Draws the layout as boxes at each key's position and size, highlighting the
+/// keys at `pressed` positions in reverse video
+pub fn render(layout: &Layout, labels: Labels, pressed: &HashSet<MatrixPosition>) -> String {
+    draw(layout, labels, |pos| {
+        pressed.contains(pos).then_some(REVERSE)
+    })
+}
+
+/// Draws the layout like `render`, coloring each key by how its count compares
+/// to the highest one. Keys without a count are left plain.
+pub fn render_heatmap(
+    layout: &Layout,
+    labels: Labels,
+    counts: &HashMap<MatrixPosition, u64>,
+) -> String {
+    let max = counts.values().copied().max().unwrap_or_default();
+
+    draw(layout, labels, |pos| {
+        let count = counts.get(pos).copied().unwrap_or_default();
+        let level = (count * HEAT.len() as u64).div_ceil(max.max(1));
+        level.checked_sub(1).map(|level| HEAT[level as usize])
+    })
+}
+
+fn draw<F>(layout: &Layout, labels: Labels, style: F) -> String
+where
+    F: Fn(&MatrixPosition) -> Option<&'static str>,
+{
+    let mut canvas = Canvas(Vec::new());
+    let height = labels.line_count() + 1;
+
+    for item in &layout.layout {
+        // Rotated keys are drawn upright around their rotated center
+        let (x, y) = item.center();
+        let (x, y) = ((x - item.w / 2.0).max(0.0), (y - item.h / 2.0).max(0.0));
+
+        let left = (x * UNIT_WIDTH).round() as usize;
+        let right = ((x + item.w) * UNIT_WIDTH).round() as usize;
+        let top = (y * height as f32).round() as usize;
+        let bottom = ((y + item.h) * height as f32).round() as usize;
+
+        canvas.horizontal(top, left, right);
+        canvas.horizontal(bottom, left, right);
+        canvas.vertical(left, top, bottom);
+        canvas.vertical(right, top, bottom);
+
+        let width = (right - left).saturating_sub(1);
+        let style = style(&item.matrix);
+
+        for (line, text) in labels.lines(item).iter().enumerate() {
+            let text = format!("{:^width$}", fit(text, width));
+            for (i, c) in text.chars().enumerate() {
+                let cell = canvas.cell(top + 1 + line, left + 1 + i);
+                cell.text = Some(c);
+                cell.style = style;
+            }
+        }
+    }
+
+    canvas.render()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::key_event::layout_diagram::{render, render_heatmap, Labels};
+    use crate::key_event::matix_mapper::{Layout, MatrixPosition};
+    use crate::test_fixtures::abc_layout;
+
+    #[test]
+    fn test_render() {
+        let layout: Layout = serde_json::from_str(
+            r#"{ "layout": [
+                { "matrix": [0, 0], "x": 0, "y": 0, "label": "ESC" },
+                { "matrix": [0, 1], "x": 1, "y": 0, "label": "LEFTSHIFT" },
+                { "matrix": [1, 0], "x": 0, "y": 1, "label": "A" }
+            ] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            render(&layout, Labels::Keys, &HashSet::new()),
+            [
+                "┌──────┬──────┐",
+                "│ ESC  │LSHIFT│",
+                "├──────┼──────┘",
+                "│  A   │",
+                "└──────┘",
+                "",
+            ]
+            .join("\n")
+        );
+
+        let pressed = HashSet::from([MatrixPosition { row: 1, col: 0 }]);
+        assert_eq!(
+            render(&layout, "matrix".parse().unwrap(), &pressed),
+            [
+                "┌──────┬──────┐",
+                "│ 0,0  │ 0,1  │",
+                "├──────┼──────┘",
+                "│\x1b[7m 1,0  \x1b[0m│",
+                "└──────┘",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_sizes() {
+        let layout: Layout = serde_json::from_str(
+            r#"{ "layout": [
+                { "matrix": [0, 0], "x": 0, "y": 0, "w": 2, "label": "BACKSPACE" },
+                { "matrix": [0, 1], "x": 2, "y": 0, "h": 2, "label": "KPPLUS" },
+                { "matrix": [1, 0], "x": 0.5, "y": 1, "w": 1.5, "label": "TAB" }
+            ] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            render(&layout, Labels::Keys, &HashSet::new()),
+            [
+                "┌─────────────┬──────┐",
+                "│  BACKSPACE  │KPPLUS│",
+                "└───┬─────────┤      │",
+                "    │   TAB   │      │",
+                "    └─────────┴──────┘",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_heatmap() {
+        let layout = abc_layout();
+        let counts = HashMap::from([
+            (MatrixPosition { row: 0, col: 0 }, 1),
+            (MatrixPosition { row: 0, col: 1 }, 60),
+        ]);
+
+        assert_eq!(
+            render_heatmap(&layout, Labels::Keys, &counts),
+            [
+                "┌──────┬──────┬──────┐",
+                "│\x1b[30;48;5;33m  A   \x1b[0m│\x1b[30;48;5;196m  B   \x1b[0m│  C   │",
+                "└──────┴──────┴──────┘",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
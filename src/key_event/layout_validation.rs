@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::matix_mapper::{Layout, LayoutItem, MatrixPosition};
+
+const MAX_SUGGESTIONS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+/// What the layout is checked against, besides itself
+#[derive(Debug, Default)]
+pub struct ValidationOptions {
+    /// Matrix size of the firmware, as `(rows, cols)`
+    pub matrix_size: Option<(u8, u8)>,
+    /// Keys the input device can send
+    pub device_keys: Option<Vec<KeyCode>>,
+}
+
+fn describe(index: usize, item: &LayoutItem) -> String {
+    format!(
+        "key #{index} {:?} at [{}, {}]",
+        item.label, item.matrix.row, item.matrix.col
+    )
+}
+
+/// Describes the item, naming the alias when the problem comes from one
+fn describe_label(index: usize, item: &LayoutItem, label: &str) -> String {
+    if label == item.label {
+        describe(index, item)
+    } else {
+        format!("alias {label:?} of {}", describe(index, item))
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Key names close to `label`, either because one contains the other, like
+/// `Shift` and `LEFTSHIFT`, or by edit distance. Single typos rank first, then
+/// containment, then the rest by distance.
+pub fn suggestions(label: &str) -> Vec<String> {
+    let label = label.to_ascii_uppercase();
+    if label.is_empty() {
+        return vec![];
+    }
+
+    let mut candidates: Vec<(u8, usize, String)> = KeyCode::all()
+        .filter(|code| *code != KeyCode::NONE)
+        .map(|code| code.as_ref().to_string())
+        .filter_map(|name| {
+            let distance = edit_distance(&label, &name);
+            let contained = (label.len() > 2 && name.contains(label.as_str()))
+                || (name.len() > 2 && label.contains(name.as_str()));
+            let rank = match (distance, contained) {
+                (0..=1, _) => 0,
+                (_, true) => 1,
+                (_, false) => 2,
+            };
+            (contained || distance <= 2).then_some((rank, distance, name))
+        })
+        .collect();
+
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, name)| name)
+        .collect()
+}
+
+pub fn validate(layout: &Layout, options: &ValidationOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut error = |message: String| {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message,
+        })
+    };
+
+    // Keys listed in several items are allowed, they press all of their positions
+    let mut warnings = Vec::new();
+    let mut keycodes: HashMap<KeyCode, usize> = HashMap::new();
+    let mut positions: HashMap<MatrixPosition, usize> = HashMap::new();
+
+    for (index, item) in layout.layout.iter().enumerate() {
+        for label in std::iter::once(&item.label).chain(item.aliases.iter()) {
+            match label.parse::<KeyCode>() {
+                Ok(keycode) => {
+                    if let Some(previous) = keycodes.insert(keycode, index) {
+                        warnings.push(format!(
+                            "{} maps the same key as key #{previous}, so the key presses both positions",
+                            describe_label(index, item, label)
+                        ));
+                    }
+                }
+                Err(_) => {
+                    let suggestions = suggestions(label);
+                    if suggestions.is_empty() {
+                        error(format!(
+                            "{} has an unknown key label",
+                            describe_label(index, item, label)
+                        ));
+                    } else {
+                        error(format!(
+                            "{} has an unknown key label, did you mean {}?",
+                            describe_label(index, item, label),
+                            suggestions.join(", ")
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(previous) = positions.insert(item.matrix, index) {
+            error(format!(
+                "{} uses the same matrix position as key #{previous}",
+                describe(index, item)
+            ));
+        }
+
+        // Extra positions are meant to be shared, so only their range is checked
+        if let Some((rows, cols)) = options.matrix_size {
+            if item.positions().any(|p| p.row >= rows || p.col >= cols) {
+                error(format!(
+                    "{} is outside of the {rows}x{cols} firmware matrix",
+                    describe(index, item)
+                ));
+            }
+        }
+    }
+
+    let mut axes = HashMap::new();
+    for (index, mapping) in layout.encoders.iter().enumerate() {
+        if let Some(previous) = axes.insert(mapping.axis, index) {
+            error(format!(
+                "encoder mapping #{index} maps the same axis {} as mapping #{previous}, which it overrides",
+                mapping.axis.as_ref()
+            ));
+        }
+    }
+
+    diagnostics.extend(warnings.into_iter().map(|message| Diagnostic {
+        severity: Severity::Warning,
+        message,
+    }));
+
+    if let Some(device_keys) = &options.device_keys {
+        let missing: Vec<&str> = device_keys
+            .iter()
+            .filter(|code| **code != KeyCode::NONE && !keycodes.contains_key(code))
+            .map(|code| code.as_ref())
+            .collect();
+
+        if !missing.is_empty() {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                message: format!(
+                    "{} keys of the input device are not in the layout: {}",
+                    missing.len(),
+                    missing.join(", ")
+                ),
+            });
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.severity);
+    diagnostics
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_event::key_code::KeyCode;
+    use crate::key_event::layout_validation::{suggestions, validate, Severity, ValidationOptions};
+    use crate::key_event::matix_mapper::Layout;
+
+    #[test]
+    fn test_validate() {
+        let layout: Layout = serde_json::from_str(
+            r#"{ "layout": [
+                { "matrix": [0, 0], "x": 0, "y": 0, "label": "ESC" },
+                { "matrix": [0, 1], "x": 1, "y": 0, "label": "Shift" },
+                { "matrix": [0, 1], "x": 2, "y": 0, "label": "esc" },
+                { "matrix": [4, 0], "x": 0, "y": 4, "label": "A" },
+                { "matrix": [1, 0], "x": 0, "y": 1, "label": "ENTER", "aliases": ["KPENTER", "a"] },
+                { "matrix": [1, 1], "x": 1, "y": 1, "label": "F13", "also": [[1, 0], [3, 5]] }
+            ], "encoders": [
+                { "axis": "REL_WHEEL", "encoder": 0 },
+                { "axis": "REL_DIAL", "encoder": 1 },
+                { "axis": "REL_WHEEL", "encoder": 1, "reverse": true }
+            ] }"#,
+        )
+        .unwrap();
+
+        let diagnostics = validate(
+            &layout,
+            &ValidationOptions {
+                matrix_size: Some((4, 4)),
+                device_keys: Some(vec![KeyCode::ESC, KeyCode::A, KeyCode::B]),
+            },
+        );
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "error: key #1 \"Shift\" at [0, 1] has an unknown key label, did you mean LEFTSHIFT, RIGHTSHIFT, FN_RIGHT_SHIFT?",
+                "error: key #2 \"esc\" at [0, 1] uses the same matrix position as key #1",
+                "error: key #3 \"A\" at [4, 0] is outside of the 4x4 firmware matrix",
+                "error: key #5 \"F13\" at [1, 1] is outside of the 4x4 firmware matrix",
+                "error: encoder mapping #2 maps the same axis REL_WHEEL as mapping #0, which it overrides",
+                "warning: key #2 \"esc\" at [0, 1] maps the same key as key #0, so the key presses both positions",
+                "warning: alias \"a\" of key #4 \"ENTER\" at [1, 0] maps the same key as key #3, so the key presses both positions",
+                "warning: 1 keys of the input device are not in the layout: B",
+            ]
+        );
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn test_suggestions() {
+        assert_eq!(suggestions("ESCAPE"), vec!["ESC", "SCALE"]);
+        assert_eq!(suggestions("backspce"), vec!["BACKSPACE", "BACK"]);
+        assert!(suggestions("").is_empty());
+    }
+}
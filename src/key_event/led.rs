@@ -0,0 +1,44 @@
+use strum_macros::{AsRefStr, EnumString, FromRepr};
+
+/*
+ * LEDs
+ */
+
+pub const LED_MAX: u16 = 0x0f;
+
+#[allow(clippy::upper_case_acronyms)]
+#[allow(non_camel_case_types)]
+#[derive(
+    FromRepr,
+    AsRefStr,
+    EnumString,
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[strum(ascii_case_insensitive)]
+#[repr(u16)]
+pub enum Led {
+    LED_NUML = 0x00,
+    LED_CAPSL = 0x01,
+    LED_SCROLLL = 0x02,
+    LED_COMPOSE = 0x03,
+    LED_KANA = 0x04,
+    LED_SLEEP = 0x05,
+    LED_SUSPEND = 0x06,
+    LED_MUTE = 0x07,
+    LED_MISC = 0x08,
+    LED_MAIL = 0x09,
+    LED_CHARGING = 0x0a,
+}
+
+impl From<Led> for u16 {
+    fn from(value: Led) -> u16 {
+        value as u16
+    }
+}
@@ -1,297 +1,231 @@
+use std::collections::HashMap;
+
 use serde::{Serialize, Deserialize};
 use serde_tuple::{Serialize_tuple, Deserialize_tuple};
 
+use crate::key_event::builtin_layouts;
 use crate::key_event::key_code::KeyCode;
+use crate::key_event::relative_axis::RelativeAxis;
 
-#[derive(Debug, Clone, Copy, Serialize_tuple, Deserialize_tuple)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize_tuple, Deserialize_tuple)]
 pub struct MatrixPosition {
     pub row: u8,
     pub col: u8,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A key of the layout. Position and size are in key units, with the same
+/// meaning as in QMK's `info.json`: keys are rotated by `r` degrees clockwise
+/// around (`rx`, `ry`) after being placed at (`x`, `y`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LayoutItem {
     pub matrix: MatrixPosition,
-    pub x: u8,
-    pub y: u8,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default = "default_size", skip_serializing_if = "is_default_size")]
+    pub w: f32,
+    #[serde(default = "default_size", skip_serializing_if = "is_default_size")]
+    pub h: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub r: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rx: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub ry: f32,
     pub label: String,
+    /// Other keys that press this same position, like both Enter keys
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Other positions pressed together with this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub also: Vec<MatrixPosition>,
+}
+
+pub fn default_size() -> f32 {
+    1.0
+}
+
+fn is_default_size(size: &f32) -> bool {
+    *size == 1.0
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+impl Default for LayoutItem {
+    fn default() -> Self {
+        Self {
+            matrix: MatrixPosition::default(),
+            x: 0.0,
+            y: 0.0,
+            w: default_size(),
+            h: default_size(),
+            r: 0.0,
+            rx: 0.0,
+            ry: 0.0,
+            label: String::new(),
+            aliases: Vec::new(),
+            also: Vec::new(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl LayoutItem {
+    /// Center of the key once rotated
+    pub fn center(&self) -> (f32, f32) {
+        let (x, y) = (self.x + self.w / 2.0 - self.rx, self.y + self.h / 2.0 - self.ry);
+        let (sin, cos) = self.r.to_radians().sin_cos();
+
+        (self.rx + x * cos - y * sin, self.ry + x * sin + y * cos)
+    }
+
+    /// Every key that presses this item, ignoring labels that are not keys
+    pub fn keycodes(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        std::iter::once(&self.label)
+            .chain(self.aliases.iter())
+            .filter_map(|label| label.parse().ok())
+    }
+
+    /// Every position this item presses
+    pub fn positions(&self) -> impl Iterator<Item = MatrixPosition> + '_ {
+        std::iter::once(self.matrix).chain(self.also.iter().copied())
+    }
+}
+
+/// Turns the steps of a relative axis, like a scroll wheel or a knob, into
+/// turns of a QMK encoder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncoderMapping {
+    pub axis: RelativeAxis,
+    pub encoder: u8,
+    /// Positive steps turn the encoder counterclockwise instead
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reverse: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Layout {
     pub layout: Vec<LayoutItem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encoders: Vec<EncoderMapping>,
 }
 
-impl From<(u8, u8)> for MatrixPosition {
-    fn from(value: (u8, u8)) -> Self {
+impl Layout {
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Reads the layout file at `name`, or the built-in layout of that name
+    /// when there is no such file
+    pub fn from_name_or_file(name: &str) -> anyhow::Result<Self> {
+        if std::path::Path::new(name).exists() {
+            return Self::from_file(name);
+        }
+
+        builtin_layouts::builtin(name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "{name} is neither a layout file nor a built-in layout ({})",
+                builtin_layouts::NAMES.join(", ")
+            )
+        })
+    }
+
+    /// Finds the matrix position of the key with the given label. Labels that
+    /// name a `KeyCode` are compared by keycode, so `esc` matches `ESC`.
+    pub fn position_of(&self, label: &str) -> Option<MatrixPosition> {
+        let keycode = label.parse::<KeyCode>().ok();
+
+        self.layout
+            .iter()
+            .find(|item| match keycode {
+                Some(keycode) => item.keycodes().any(|k| k == keycode),
+                None => item.label == label,
+            })
+            .map(|item| item.matrix)
+    }
+}
+
+/// Lookup table from evdev keycodes to the positions they press in the matrix.
+/// A key can press several positions, and several keys can press the same one.
+pub struct MatrixMapper {
+    keys: HashMap<KeyCode, Vec<MatrixPosition>>,
+    encoders: HashMap<RelativeAxis, EncoderMapping>,
+}
+
+impl MatrixMapper {
+    pub fn new(layout: &Layout) -> Self {
+        let mut positions: HashMap<KeyCode, Vec<MatrixPosition>> = HashMap::new();
+
+        layout.layout.iter().for_each(|layout_item| {
+            for keycode in layout_item.keycodes() {
+                let key_positions = positions.entry(keycode).or_default();
+                for position in layout_item.positions() {
+                    if !key_positions.contains(&position) {
+                        key_positions.push(position);
+                    }
+                }
+            }
+        });
+
+        let encoders = layout
+            .encoders
+            .iter()
+            .map(|mapping| (mapping.axis, mapping.clone()))
+            .collect();
+
         Self {
-            col: value.0,
-            row: value.1,
+            keys: positions,
+            encoders,
         }
     }
+
+    pub fn positions(&self, keycode: KeyCode) -> &[MatrixPosition] {
+        self.keys.get(&keycode).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn encoder(&self, axis: RelativeAxis) -> Option<&EncoderMapping> {
+        self.encoders.get(&axis)
+    }
 }
 
-pub fn map(keycode: KeyCode) -> MatrixPosition {
-    /*
-     * ┌───┐   ┌───┬───┬───┬───┐ ┌───┬───┬───┬───┐ ┌───┬───┬───┬───┐
-     * │Esc│   │F1 │F2 │F3 │F4 │ │F5 │F6 │F7 │F8 │ │F9 │F10│F11│F12│
-     * └───┘   └───┴───┴───┴───┘ └───┴───┴───┴───┘ └───┴───┴───┴───┘
-     * ┌───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───┬───────┐ ┌───┬───┬───┬───┐
-     * │ ` │ 1 │ 2 │ 3 │ 4 │ 5 │ 6 │ 7 │ 8 │ 9 │ 0 │ - │ = │ Backsp│ │Num│ / │ * │ - │
-     * ├───┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─────┤ ├───┼───┼───┼───┤
-     * │ Tab │ Q │ W │ E │ R │ T │ Y │ U │ I │ O │ P │ [ │ ] │  \  │ │ 7 │ 8 │ 9 │   │
-     * ├─────┴┬──┴┬──┴┬──┴┬──┴┬──┴┬──┴┬──┴┬──┴┬──┴┬──┴┬──┴┬──┴─────┤ ├───┼───┼───┤ + │
-     * │ Caps │ A │ S │ D │ F │ G │ H │ J │ K │ L │ ; │ ' │  Enter │ │ 4 │ 5 │ 6 │   │
-     * ├──────┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴─┬─┴────────┤ ├───┼───┼───┼───┤
-     * │ Shift  │ Z │ X │ C │ V │ B │ N │ M │ , │ . │ / │    Shift │ │ 1 │ 2 │ 3 │   │
-     * ├────┬───┴┬──┴─┬─┴───┴───┴───┴───┴───┴──┬┴───┼───┴┬────┬────┤ ├───┴───┼───┤Ent│
-     * │Ctrl│GUI │Alt │                        │ Alt│ GUI│Menu│Ctrl│ │   0   │ . │   │
-     * └────┴────┴────┴────────────────────────┴────┴────┴────┴────┘ └───────┴───┴───┘
-     */
+/// Converts a `(row, col)` pair
+impl From<(u8, u8)> for MatrixPosition {
+    fn from((row, col): (u8, u8)) -> Self {
+        Self { row, col }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_event::key_code::KeyCode;
+    use crate::key_event::matix_mapper::{Layout, MatrixMapper, MatrixPosition};
+
+    #[test]
+    fn test_aliases_and_extra_positions() {
+        let layout: Layout = serde_json::from_str(
+            r#"{ "layout": [
+                { "matrix": [1, 0], "x": 0, "y": 1, "label": "ENTER", "aliases": ["KPENTER"] },
+                { "matrix": [2, 3], "x": 3, "y": 2, "label": "F13", "also": [[0, 0], [2, 4]] },
+                { "matrix": [3, 0], "x": 0, "y": 3, "label": "FN", "aliases": ["BTN_SIDE"] }
+            ] }"#,
+        )
+        .unwrap();
+        let mapper = MatrixMapper::new(&layout);
 
-    match keycode {
-        KeyCode::ESC              => (0, 0),
-        KeyCode::F1               => (1, 0),
-        KeyCode::F2               => (2, 0),
-        KeyCode::F3               => (3, 0),
-        KeyCode::F4               => (4, 0),
-        KeyCode::F5               => (5, 0),
-        KeyCode::F6               => (6, 0),
-        KeyCode::F7               => (7, 0),
-        KeyCode::F8               => (8, 0),
-        KeyCode::F9               => (9, 0),
-        KeyCode::F10              => (10,0),
-        KeyCode::F11              => (11,0),
-        KeyCode::F12              => (12,0),
-        KeyCode::GRAVE            => (0, 1),
-        KeyCode::KEY_1            => (1, 1),
-        KeyCode::KEY_2            => (2, 1),
-        KeyCode::KEY_3            => (3, 1),
-        KeyCode::KEY_4            => (4, 1),
-        KeyCode::KEY_5            => (5, 1),
-        KeyCode::KEY_6            => (6, 1),
-        KeyCode::KEY_7            => (7, 1),
-        KeyCode::KEY_8            => (8, 1),
-        KeyCode::KEY_9            => (9, 1),
-        KeyCode::KEY_0            => (10,1),
-        KeyCode::MINUS            => (11,1),
-        KeyCode::EQUAL            => (12,1),
-        KeyCode::BACKSPACE        => (13,1),
-        KeyCode::TAB              => (2, 0),
-        KeyCode::Q                => (2, 1),
-        KeyCode::W                => (2, 2),
-        KeyCode::E                => (2, 3),
-        KeyCode::R                => (2, 4),
-        KeyCode::T                => (2, 5),
-        KeyCode::Y                => (2, 6),
-        KeyCode::U                => (2, 7),
-        KeyCode::I                => (2, 8),
-        KeyCode::O                => (2, 9),
-        KeyCode::P                => (2, 10),
-        KeyCode::LEFTBRACE        => (2, 11),
-        KeyCode::RIGHTBRACE       => (2, 12),
-        KeyCode::ENTER            => (2, 13),
-        KeyCode::LEFTCTRL         => (0, 0),
-        KeyCode::A                => (0, 0),
-        KeyCode::S                => (0, 0),
-        KeyCode::D                => (0, 0),
-        KeyCode::F                => (0, 0),
-        KeyCode::G                => (0, 0),
-        KeyCode::H                => (0, 0),
-        KeyCode::J                => (0, 0),
-        KeyCode::K                => (0, 0),
-        KeyCode::L                => (0, 0),
-        KeyCode::SEMICOLON        => (0, 0),
-        KeyCode::APOSTROPHE       => (0, 0),
-        KeyCode::LEFTSHIFT        => (0, 0),
-        KeyCode::BACKSLASH        => (0, 0),
-        KeyCode::Z                => (0, 0),
-        KeyCode::X                => (0, 0),
-        KeyCode::C                => (0, 0),
-        KeyCode::V                => (0, 0),
-        KeyCode::B                => (0, 0),
-        KeyCode::N                => (0, 0),
-        KeyCode::M                => (0, 0),
-        KeyCode::COMMA            => (0, 0),
-        KeyCode::DOT              => (0, 0),
-        KeyCode::SLASH            => (0, 0),
-        KeyCode::RIGHTSHIFT       => (0, 0),
-        KeyCode::KPASTERISK       => (0, 0),
-        KeyCode::LEFTALT          => (0, 0),
-        KeyCode::SPACE            => (0, 0),
-        KeyCode::CAPSLOCK         => (0, 0),
-        KeyCode::NUMLOCK          => (0, 0),
-        KeyCode::SCROLLLOCK       => (0, 0),
-        KeyCode::KP7              => (0, 0),
-        KeyCode::KP8              => (0, 0),
-        KeyCode::KP9              => (0, 0),
-        KeyCode::KPMINUS          => (0, 0),
-        KeyCode::KP4              => (0, 0),
-        KeyCode::KP5              => (0, 0),
-        KeyCode::KP6              => (0, 0),
-        KeyCode::KPPLUS           => (0, 0),
-        KeyCode::KP1              => (0, 0),
-        KeyCode::KP2              => (0, 0),
-        KeyCode::KP3              => (0, 0),
-        KeyCode::KP0              => (0, 0),
-        KeyCode::KPDOT            => (0, 0),
-        KeyCode::ZENKAKUHANKAKU   => (0, 0),
-        KeyCode::KEY_102ND        => (0, 0),
-        KeyCode::RO               => (0, 0),
-        KeyCode::KATAKANA         => (0, 0),
-        KeyCode::HIRAGANA         => (0, 0),
-        KeyCode::HENKAN           => (0, 0),
-        KeyCode::KATAKANAHIRAGANA => (0, 0),
-        KeyCode::MUHENKAN         => (0, 0),
-        KeyCode::KPJPCOMMA        => (0, 0),
-        KeyCode::KPENTER          => (0, 0),
-        KeyCode::RIGHTCTRL        => (0, 0),
-        KeyCode::KPSLASH          => (0, 0),
-        KeyCode::SYSRQ            => (0, 0),
-        KeyCode::RIGHTALT         => (0, 0),
-        KeyCode::LINEFEED         => (0, 0),
-        KeyCode::HOME             => (0, 0),
-        KeyCode::UP               => (0, 0),
-        KeyCode::PAGEUP           => (0, 0),
-        KeyCode::LEFT             => (0, 0),
-        KeyCode::RIGHT            => (0, 0),
-        KeyCode::END              => (0, 0),
-        KeyCode::DOWN             => (0, 0),
-        KeyCode::PAGEDOWN         => (0, 0),
-        KeyCode::INSERT           => (0, 0),
-        KeyCode::DELETE           => (0, 0),
-        KeyCode::MACRO            => (0, 0),
-        KeyCode::MUTE             => (0, 0),
-        KeyCode::VOLUMEDOWN       => (0, 0),
-        KeyCode::VOLUMEUP         => (0, 0),
-        KeyCode::POWER            => (0, 0),
-        KeyCode::KPEQUAL          => (0, 0),
-        KeyCode::KPPLUSMINUS      => (0, 0),
-        KeyCode::PAUSE            => (0, 0),
-        KeyCode::SCALE            => (0, 0),
-        KeyCode::KPCOMMA          => (0, 0),
-        KeyCode::HANGEUL          => (0, 0),
-        KeyCode::HANJA            => (0, 0),
-        KeyCode::YEN              => (0, 0),
-        KeyCode::LEFTMETA         => (0, 0),
-        KeyCode::RIGHTMETA        => (0, 0),
-        KeyCode::COMPOSE          => (0, 0),
-        KeyCode::STOP             => (0, 0),
-        KeyCode::AGAIN            => (0, 0),
-        KeyCode::PROPS            => (0, 0),
-        KeyCode::UNDO             => (0, 0),
-        KeyCode::FRONT            => (0, 0),
-        KeyCode::COPY             => (0, 0),
-        KeyCode::OPEN             => (0, 0),
-        KeyCode::PASTE            => (0, 0),
-        KeyCode::FIND             => (0, 0),
-        KeyCode::CUT              => (0, 0),
-        KeyCode::HELP             => (0, 0),
-        KeyCode::MENU             => (0, 0),
-        KeyCode::CALC             => (0, 0),
-        KeyCode::SETUP            => (0, 0),
-        KeyCode::SLEEP            => (0, 0),
-        KeyCode::WAKEUP           => (0, 0),
-        KeyCode::FILE             => (0, 0),
-        KeyCode::SENDFILE         => (0, 0),
-        KeyCode::DELETEFILE       => (0, 0),
-        KeyCode::XFER             => (0, 0),
-        KeyCode::PROG1            => (0, 0),
-        KeyCode::PROG2            => (0, 0),
-        KeyCode::WWW              => (0, 0),
-        KeyCode::MSDOS            => (0, 0),
-        KeyCode::COFFEE           => (0, 0),
-        KeyCode::ROTATE_DISPLAY   => (0, 0),
-        KeyCode::CYCLEWINDOWS     => (0, 0),
-        KeyCode::MAIL             => (0, 0),
-        KeyCode::BOOKMARKS        => (0, 0),
-        KeyCode::COMPUTER         => (0, 0),
-        KeyCode::BACK             => (0, 0),
-        KeyCode::FORWARD          => (0, 0),
-        KeyCode::CLOSECD          => (0, 0),
-        KeyCode::EJECTCD          => (0, 0),
-        KeyCode::EJECTCLOSECD     => (0, 0),
-        KeyCode::NEXTSONG         => (0, 0),
-        KeyCode::PLAYPAUSE        => (0, 0),
-        KeyCode::PREVIOUSSONG     => (0, 0),
-        KeyCode::STOPCD           => (0, 0),
-        KeyCode::RECORD           => (0, 0),
-        KeyCode::REWIND           => (0, 0),
-        KeyCode::PHONE            => (0, 0),
-        KeyCode::ISO              => (0, 0),
-        KeyCode::CONFIG           => (0, 0),
-        KeyCode::HOMEPAGE         => (0, 0),
-        KeyCode::REFRESH          => (0, 0),
-        KeyCode::EXIT             => (0, 0),
-        KeyCode::MOVE             => (0, 0),
-        KeyCode::EDIT             => (0, 0),
-        KeyCode::SCROLLUP         => (0, 0),
-        KeyCode::SCROLLDOWN       => (0, 0),
-        KeyCode::KPLEFTPAREN      => (0, 0),
-        KeyCode::KPRIGHTPAREN     => (0, 0),
-        KeyCode::NEW              => (0, 0),
-        KeyCode::REDO             => (0, 0),
-        KeyCode::F13              => (0, 0),
-        KeyCode::F14              => (0, 0),
-        KeyCode::F15              => (0, 0),
-        KeyCode::F16              => (0, 0),
-        KeyCode::F17              => (0, 0),
-        KeyCode::F18              => (0, 0),
-        KeyCode::F19              => (0, 0),
-        KeyCode::F20              => (0, 0),
-        KeyCode::F21              => (0, 0),
-        KeyCode::F22              => (0, 0),
-        KeyCode::F23              => (0, 0),
-        KeyCode::F24              => (0, 0),
-        KeyCode::PLAYCD           => (0, 0),
-        KeyCode::PAUSECD          => (0, 0),
-        KeyCode::PROG3            => (0, 0),
-        KeyCode::PROG4            => (0, 0),
-        KeyCode::ALL_APPLICATIONS => (0, 0),
-        KeyCode::SUSPEND          => (0, 0),
-        KeyCode::CLOSE            => (0, 0),
-        KeyCode::PLAY             => (0, 0),
-        KeyCode::FASTFORWARD      => (0, 0),
-        KeyCode::BASSBOOST        => (0, 0),
-        KeyCode::HP               => (0, 0),
-        KeyCode::CAMERA           => (0, 0),
-        KeyCode::SOUND            => (0, 0),
-        KeyCode::QUESTION         => (0, 0),
-        KeyCode::EMAIL            => (0, 0),
-        KeyCode::CHAT             => (0, 0),
-        KeyCode::SEARCH           => (0, 0),
-        KeyCode::CONNECT          => (0, 0),
-        KeyCode::FINANCE          => (0, 0),
-        KeyCode::SPORT            => (0, 0),
-        KeyCode::SHOP             => (0, 0),
-        KeyCode::ALTERASE         => (0, 0),
-        KeyCode::CANCEL           => (0, 0),
-        KeyCode::BRIGHTNESSDOWN   => (0, 0),
-        KeyCode::BRIGHTNESSUP     => (0, 0),
-        KeyCode::MEDIA            => (0, 0),
-        KeyCode::SWITCHVIDEOMODE  => (0, 0),
-        KeyCode::KBDILLUMTOGGLE   => (0, 0),
-        KeyCode::KBDILLUMDOWN     => (0, 0),
-        KeyCode::KBDILLUMUP       => (0, 0),
-        KeyCode::SEND             => (0, 0),
-        KeyCode::REPLY            => (0, 0),
-        KeyCode::FORWARDMAIL      => (0, 0),
-        KeyCode::SAVE             => (0, 0),
-        KeyCode::DOCUMENTS        => (0, 0),
-        KeyCode::BATTERY          => (0, 0),
-        KeyCode::BLUETOOTH        => (0, 0),
-        KeyCode::WLAN             => (0, 0),
-        KeyCode::UWB              => (0, 0),
-        KeyCode::UNKNOWN          => (0, 0),
-        KeyCode::VIDEO_NEXT       => (0, 0),
-        KeyCode::VIDEO_PREV       => (0, 0),
-        KeyCode::BRIGHTNESS_CYCLE => (0, 0),
-        KeyCode::BRIGHTNESS_AUTO  => (0, 0),
-        KeyCode::DISPLAY_OFF      => (0, 0),
-        KeyCode::WWAN             => (0, 0),
-        KeyCode::RFKILL           => (0, 0),
-        KeyCode::MICMUTE          => (0, 0),
-        _ => (0, 0),
+        assert_eq!(mapper.positions(KeyCode::ENTER), &[MatrixPosition { row: 1, col: 0 }]);
+        assert_eq!(mapper.positions(KeyCode::KPENTER), &[MatrixPosition { row: 1, col: 0 }]);
+        assert_eq!(
+            mapper.positions(KeyCode::F13),
+            &[
+                MatrixPosition { row: 2, col: 3 },
+                MatrixPosition { row: 0, col: 0 },
+                MatrixPosition { row: 2, col: 4 }
+            ]
+        );
+        assert!(mapper.positions(KeyCode::A).is_empty());
+        assert_eq!(mapper.positions(KeyCode::FN), &[MatrixPosition { row: 3, col: 0 }]);
+        assert_eq!(mapper.positions(KeyCode::BTN_SIDE), &[MatrixPosition { row: 3, col: 0 }]);
+        assert_eq!(layout.position_of("kpenter"), Some(MatrixPosition { row: 1, col: 0 }));
     }
-    .into()
 }
@@ -1,11 +1,24 @@
+pub mod absolute_axis;
+pub mod builtin_layouts;
 pub mod key_code;
+pub mod kle;
+pub mod layout_diagram;
+pub mod layout_validation;
+pub mod led;
 pub mod matix_mapper;
+pub mod qmk_info;
+pub mod relative_axis;
 
 use std::time::{Duration, SystemTime};
 
 use nix::libc::{input_event, timeval};
 
+use strum_macros::FromRepr;
+
+use self::absolute_axis::AbsoluteAxis;
 use self::key_code::KeyCode;
+use self::led::Led;
+use self::relative_axis::RelativeAxis;
 
 pub enum IOctlOp {
     Ungrab = 0,
@@ -13,26 +26,44 @@ pub enum IOctlOp {
 }
 
 pub const EVIOCGRAB: u64 = 0x40044590;
+pub const EVIOCGID: u64 = 0x80084502;
+
+/// EVIOCGNAME(len), reading the device name into a buffer of `len` bytes
+pub const fn eviocgname(len: usize) -> u64 {
+    0x80004506 | (len as u64) << 16
+}
+
+/// EVIOCGBIT(event_type, len), reading the bitmask of the codes of
+/// `event_type` a device can send, or of the event types themselves for 0
+pub const fn eviocgbit(event_type: u16, len: usize) -> u64 {
+    0x80004520 | (len as u64) << 16 | event_type as u64
+}
+
+pub const EV_MAX: u16 = 0x1f;
 
 // input_event types
 pub const EV_SYN: u16 = 0x00;
 pub const EV_KEY: u16 = 0x01;
-// pub const EV_REL: u16 = 0x02;
-// pub const EV_ABS: u16 = 0x03;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
 pub const EV_MSC: u16 = 0x04;
 // pub const EV_SW: u16 = 0x05;
-// pub const EV_LED: u16 = 0x11;
+pub const EV_LED: u16 = 0x11;
 // pub const EV_SND: u16 = 0x12;
-// pub const EV_REP: u16 = 0x14;
+pub const EV_REP: u16 = 0x14;
 // pub const EV_FF: u16 = 0x15;
 // pub const EV_PWR: u16 = 0x16;
 // pub const EV_FF_STATUS: u16 = 0x17;
 
 // Sync events
 pub const SYN_REPORT: u16 = 0;
-// pub const SYN_CONFIG: u16 = 1;
-// pub const SYN_MT_REPORT: u16 = 2;
-// pub const SYN_DROPPED: u16 = 3;
+pub const SYN_CONFIG: u16 = 1;
+pub const SYN_MT_REPORT: u16 = 2;
+pub const SYN_DROPPED: u16 = 3;
+
+// Autorepeat settings
+pub const REP_DELAY: u16 = 0x00;
+pub const REP_PERIOD: u16 = 0x01;
 
 // Misc events
 // pub const MSC_SERIAL: u16 = 0x00;
@@ -46,7 +77,7 @@ pub const KEY_RELEASE: i32 = 0;
 pub const KEY_PRESS: i32 = 1;
 pub const KEY_HOLD: i32 = 2;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MyTime(SystemTime);
 
 impl Default for MyTime {
@@ -55,11 +86,58 @@ impl Default for MyTime {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl MyTime {
+    /// Time since this, zero if it is in the future
+    pub fn elapsed(&self) -> Duration {
+        self.0.elapsed().unwrap_or_default()
+    }
+
+    /// Time from `earlier` to this, zero if `earlier` is later
+    pub fn since(&self, earlier: MyTime) -> Duration {
+        self.0.duration_since(earlier.0).unwrap_or_default()
+    }
+
+    /// Like `since`, but `None` when `earlier` is actually later
+    pub fn checked_since(&self, earlier: MyTime) -> Option<Duration> {
+        self.0.duration_since(earlier.0).ok()
+    }
+
+    /// The time `duration` after this
+    pub fn after(&self, duration: Duration) -> MyTime {
+        Self(self.0 + duration)
+    }
+}
+
+#[derive(FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum SyncKind {
+    /// Ends a batch of events that happened at the same time
+    Report = SYN_REPORT,
+    Config = SYN_CONFIG,
+    MtReport = SYN_MT_REPORT,
+    /// The kernel's buffer overflowed and events since the last report were
+    /// lost, the device state has to be queried again
+    Dropped = SYN_DROPPED,
+}
+
+#[derive(FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum RepeatSetting {
+    /// Milliseconds before a held key starts repeating
+    Delay = REP_DELAY,
+    /// Milliseconds between repeats
+    Period = REP_PERIOD,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum KeyEvent {
     Press(KeyCode, bool, MyTime),
     Release(KeyCode, MyTime),
-    Sync(i32, MyTime),
+    Relative(RelativeAxis, i32, MyTime),
+    Absolute(AbsoluteAxis, i32, MyTime),
+    Led(Led, bool, MyTime),
+    Repeat(RepeatSetting, i32, MyTime),
+    Sync(SyncKind, i32, MyTime),
     Scancode(i32, MyTime),
     Unsupported(u16, u16, i32, MyTime),
 }
@@ -67,7 +145,6 @@ pub enum KeyEvent {
 impl From<input_event> for KeyEvent {
     fn from(value: input_event) -> Self {
         match (value.type_, value.code, value.value) {
-            (EV_SYN, SYN_REPORT, v) => KeyEvent::Sync(v, value.time.into()),
             (EV_MSC, MSC_SCAN, v) => KeyEvent::Scancode(v, value.time.into()),
             (EV_KEY, code, action) => match (KeyCode::from_repr(code), action) {
                 (Some(code), KEY_RELEASE) => KeyEvent::Release(code, value.time.into()),
@@ -75,6 +152,26 @@ impl From<input_event> for KeyEvent {
                 (Some(code), KEY_HOLD) => KeyEvent::Press(code, true, value.time.into()),
                 (_, _) => KeyEvent::Unsupported(EV_KEY, code, action, value.time.into()),
             },
+            (EV_REL, code, v) => match RelativeAxis::from_repr(code) {
+                Some(axis) => KeyEvent::Relative(axis, v, value.time.into()),
+                None => KeyEvent::Unsupported(EV_REL, code, v, value.time.into()),
+            },
+            (EV_ABS, code, v) => match AbsoluteAxis::from_repr(code) {
+                Some(axis) => KeyEvent::Absolute(axis, v, value.time.into()),
+                None => KeyEvent::Unsupported(EV_ABS, code, v, value.time.into()),
+            },
+            (EV_LED, code, v) => match Led::from_repr(code) {
+                Some(led) => KeyEvent::Led(led, v != 0, value.time.into()),
+                None => KeyEvent::Unsupported(EV_LED, code, v, value.time.into()),
+            },
+            (EV_REP, code, v) => match RepeatSetting::from_repr(code) {
+                Some(setting) => KeyEvent::Repeat(setting, v, value.time.into()),
+                None => KeyEvent::Unsupported(EV_REP, code, v, value.time.into()),
+            },
+            (EV_SYN, code, v) => match SyncKind::from_repr(code) {
+                Some(kind) => KeyEvent::Sync(kind, v, value.time.into()),
+                None => KeyEvent::Unsupported(EV_SYN, code, v, value.time.into()),
+            },
             (type_, code, v) => KeyEvent::Unsupported(type_, code, v, value.time.into()),
         }
     }
@@ -82,7 +179,10 @@ impl From<input_event> for KeyEvent {
 
 impl From<timeval> for MyTime {
     fn from(value: timeval) -> Self {
-        Self(SystemTime::UNIX_EPOCH + Duration::new(value.tv_sec as u64, value.tv_usec as u32))
+        Self(
+            SystemTime::UNIX_EPOCH
+                + Duration::new(value.tv_sec as u64, value.tv_usec as u32 * 1000),
+        )
     }
 }
 
@@ -118,10 +218,34 @@ impl From<KeyEvent> for input_event {
                 code: code.into(),
                 value: KEY_RELEASE,
             },
-            KeyEvent::Sync(value, time) => input_event {
+            KeyEvent::Relative(axis, value, time) => input_event {
+                time: time.into(),
+                type_: EV_REL,
+                code: axis.into(),
+                value,
+            },
+            KeyEvent::Absolute(axis, value, time) => input_event {
+                time: time.into(),
+                type_: EV_ABS,
+                code: axis.into(),
+                value,
+            },
+            KeyEvent::Led(led, on, time) => input_event {
+                time: time.into(),
+                type_: EV_LED,
+                code: led.into(),
+                value: on as i32,
+            },
+            KeyEvent::Repeat(setting, value, time) => input_event {
+                time: time.into(),
+                type_: EV_REP,
+                code: setting as u16,
+                value,
+            },
+            KeyEvent::Sync(kind, value, time) => input_event {
                 time: time.into(),
                 type_: EV_SYN,
-                code: SYN_REPORT,
+                code: kind as u16,
                 value,
             },
             KeyEvent::Scancode(value, time) => input_event {
@@ -139,3 +263,114 @@ impl From<KeyEvent> for input_event {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use nix::libc::{input_event, timeval};
+
+    use crate::key_event::absolute_axis::AbsoluteAxis;
+    use crate::key_event::key_code::{KeyCode, KEY_MAX};
+    use crate::key_event::led::Led;
+    use crate::key_event::relative_axis::RelativeAxis;
+    use crate::key_event::{
+        eviocgbit, eviocgname, KeyEvent, RepeatSetting, SyncKind, EV_KEY, EV_MAX, EV_SYN,
+    };
+
+    fn raw(type_: u16, code: u16, value: i32) -> input_event {
+        input_event {
+            time: timeval {
+                tv_sec: 1700000000,
+                tv_usec: 250,
+            },
+            type_,
+            code,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let t = raw(0, 0, 0).time.into();
+        let events = [
+            (raw(0x01, 0x1e, 1), KeyEvent::Press(KeyCode::A, false, t)),
+            (raw(0x01, 0x1e, 2), KeyEvent::Press(KeyCode::A, true, t)),
+            (raw(0x01, 0x116, 0), KeyEvent::Release(KeyCode::BTN_BACK, t)),
+            (
+                raw(0x02, 0x08, -2),
+                KeyEvent::Relative(RelativeAxis::REL_WHEEL, -2, t),
+            ),
+            (
+                raw(0x03, 0x35, 640),
+                KeyEvent::Absolute(AbsoluteAxis::ABS_MT_POSITION_X, 640, t),
+            ),
+            (raw(0x11, 0x01, 1), KeyEvent::Led(Led::LED_CAPSL, true, t)),
+            (
+                raw(0x14, 0x00, 250),
+                KeyEvent::Repeat(RepeatSetting::Delay, 250, t),
+            ),
+            (
+                raw(0x14, 0x01, 33),
+                KeyEvent::Repeat(RepeatSetting::Period, 33, t),
+            ),
+            (raw(0x00, 0x00, 0), KeyEvent::Sync(SyncKind::Report, 0, t)),
+            (raw(0x00, 0x03, 0), KeyEvent::Sync(SyncKind::Dropped, 0, t)),
+            (raw(0x04, 0x04, 0x70004), KeyEvent::Scancode(0x70004, t)),
+            (raw(0x03, 0x3f, 1), KeyEvent::Unsupported(3, 63, 1, t)),
+            (raw(0x05, 0x00, 1), KeyEvent::Unsupported(5, 0, 1, t)),
+        ];
+
+        for (event, expected) in events {
+            let parsed = KeyEvent::from(event);
+            assert_eq!(parsed, expected);
+
+            let back: input_event = parsed.into();
+            assert_eq!(
+                (back.type_, back.code, back.value),
+                (event.type_, event.code, event.value)
+            );
+            assert_eq!(
+                (back.time.tv_sec, back.time.tv_usec),
+                (event.time.tv_sec, event.time.tv_usec)
+            );
+        }
+    }
+
+    #[test]
+    fn test_eviocgbit() {
+        assert_eq!(eviocgbit(EV_KEY, KEY_MAX as usize / 8 + 1), 0x80604521);
+        assert_eq!(eviocgbit(EV_SYN, EV_MAX as usize / 8 + 1), 0x80044520);
+        assert_eq!(eviocgname(256), 0x81004506);
+    }
+
+    #[test]
+    fn test_typed_variants() {
+        assert!(matches!(
+            KeyEvent::from(raw(0x00, 0x03, 0)),
+            KeyEvent::Sync(SyncKind::Dropped, 0, _)
+        ));
+        assert!(matches!(
+            KeyEvent::from(raw(0x11, 0x00, 0)),
+            KeyEvent::Led(Led::LED_NUML, false, _)
+        ));
+        assert!(matches!(
+            KeyEvent::from(raw(0x14, 0x01, 33)),
+            KeyEvent::Repeat(RepeatSetting::Period, 33, _)
+        ));
+        assert!(matches!(
+            KeyEvent::from(raw(0x03, 0x00, 12)),
+            KeyEvent::Absolute(AbsoluteAxis::ABS_X, 12, _)
+        ));
+        assert!(matches!(
+            KeyEvent::from(raw(0x02, 0x0b, 120)),
+            KeyEvent::Relative(RelativeAxis::REL_WHEEL_HI_RES, 120, _)
+        ));
+        assert!(matches!(
+            KeyEvent::from(raw(0x01, 0x2ff, 1)),
+            KeyEvent::Unsupported(0x01, 0x2ff, 1, _)
+        ));
+        assert!(matches!(
+            KeyEvent::from(raw(0x01, 0x1c, 0)),
+            KeyEvent::Release(KeyCode::ENTER, _)
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+
+use crate::key_event::matix_mapper::{default_size, Layout, LayoutItem, MatrixPosition};
+
+/// The parts of a QMK `info.json` / `keyboard.json` needed to build a `Layout`
+#[derive(Debug, Deserialize)]
+pub struct QmkInfo {
+    pub matrix_size: Option<MatrixSize>,
+    #[serde(default)]
+    pub layout_aliases: BTreeMap<String, String>,
+    pub layouts: BTreeMap<String, QmkLayout>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct MatrixSize {
+    pub rows: u8,
+    pub cols: u8,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QmkLayout {
+    pub layout: Vec<QmkLayoutKey>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QmkLayoutKey {
+    pub matrix: MatrixPosition,
+    pub x: f32,
+    pub y: f32,
+    #[serde(default = "default_size")]
+    pub w: f32,
+    #[serde(default = "default_size")]
+    pub h: f32,
+    #[serde(default)]
+    pub r: f32,
+    #[serde(default)]
+    pub rx: f32,
+    #[serde(default)]
+    pub ry: f32,
+}
+
+/// Which evdev key drives each key of a QMK layout, by index in the layout
+/// macro. Either a list in layout order, like a `keymap.json` layer, with
+/// `null` for keys that are not driven by anything:
+///
+/// ```json
+/// ["ESC", "F1", null, "F2"]
+/// ```
+///
+/// or a map from key label to index:
+///
+/// ```json
+/// { "ESC": 0, "F1": 1, "F2": 3 }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum KeyIndex {
+    List(Vec<Option<String>>),
+    Map(BTreeMap<String, usize>),
+}
+
+impl KeyIndex {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    fn labels(&self) -> Vec<(usize, &str)> {
+        match self {
+            Self::List(labels) => labels
+                .iter()
+                .enumerate()
+                .filter_map(|(i, label)| label.as_deref().map(|l| (i, l)))
+                .collect(),
+            Self::Map(indices) => indices.iter().map(|(l, i)| (*i, l.as_str())).collect(),
+        }
+    }
+}
+
+impl QmkInfo {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Finds a layout macro by name, following aliases. Without a name the
+    /// keyboard must have a single layout.
+    pub fn find_layout(&self, name: Option<&str>) -> anyhow::Result<&QmkLayout> {
+        let available = || self.layouts.keys().cloned().collect::<Vec<_>>().join(", ");
+
+        match name {
+            Some(name) => {
+                let name = self.layout_aliases.get(name).map_or(name, String::as_str);
+                self.layouts
+                    .get(name)
+                    .ok_or_else(|| anyhow!("no layout {name}, available: {}", available()))
+            }
+            None if self.layouts.len() == 1 => Ok(self.layouts.values().next().unwrap()),
+            None => bail!("choose one of the layouts: {}", available()),
+        }
+    }
+
+    /// Builds a `Layout` from the named layout macro, labeling each key with
+    /// the evdev key that drives it according to `keys`. Further keys mapped
+    /// to the same index become aliases of the first one.
+    pub fn to_layout(&self, name: Option<&str>, keys: &KeyIndex) -> anyhow::Result<Layout> {
+        let qmk_layout = self.find_layout(name)?;
+        let mut layout: Vec<LayoutItem> = Vec::new();
+        // Item of the layout made for each index
+        let mut items: HashMap<usize, usize> = HashMap::new();
+
+        for (index, label) in keys.labels() {
+            if let Some(item) = items.get(&index) {
+                layout[*item].aliases.push(label.to_string());
+                continue;
+            }
+
+            let key = qmk_layout.layout.get(index).ok_or_else(|| {
+                anyhow!(
+                    "{label} is mapped to index {index} but the layout has {} keys",
+                    qmk_layout.layout.len()
+                )
+            })?;
+
+            items.insert(index, layout.len());
+            layout.push(LayoutItem {
+                matrix: key.matrix,
+                x: key.x,
+                y: key.y,
+                w: key.w,
+                h: key.h,
+                r: key.r,
+                rx: key.rx,
+                ry: key.ry,
+                label: label.to_string(),
+                ..Default::default()
+            });
+        }
+
+        Ok(Layout {
+            layout,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_event::qmk_info::{KeyIndex, QmkInfo};
+
+    const INFO: &str = r#"{
+        "keyboard_name": "test",
+        "matrix_size": { "rows": 2, "cols": 2 },
+        "layout_aliases": { "LAYOUT": "LAYOUT_all" },
+        "layouts": {
+            "LAYOUT_all": {
+                "layout": [
+                    { "matrix": [0, 0], "x": 0, "y": 0, "label": "Esc" },
+                    { "matrix": [0, 1], "x": 1.25, "y": 0 },
+                    { "matrix": [1, 1], "x": 0, "y": 1, "w": 2.25 }
+                ]
+            },
+            "LAYOUT_small": { "layout": [] }
+        }
+    }"#;
+
+    #[test]
+    fn test_to_layout() {
+        let info: QmkInfo = serde_json::from_str(INFO).unwrap();
+
+        let list: KeyIndex = serde_json::from_str(r#"["ESC", null, "SPACE"]"#).unwrap();
+        let map: KeyIndex = serde_json::from_str(r#"{ "ESC": 0, "SPACE": 2 }"#).unwrap();
+
+        for keys in [list, map] {
+            let layout = info.to_layout(Some("LAYOUT"), &keys).unwrap();
+            let labels: Vec<_> = layout.layout.iter().map(|i| i.label.as_str()).collect();
+
+            assert_eq!(labels, vec!["ESC", "SPACE"]);
+            assert_eq!(
+                layout.position_of("SPACE").map(|p| (p.row, p.col)),
+                Some((1, 1))
+            );
+            assert_eq!((layout.layout[1].w, layout.layout[1].h), (2.25, 1.0));
+        }
+    }
+
+    #[test]
+    fn test_keys_sharing_an_index() {
+        let info: QmkInfo = serde_json::from_str(INFO).unwrap();
+        let keys: KeyIndex =
+            serde_json::from_str(r#"{ "ENTER": 2, "ESC": 0, "KPENTER": 2 }"#).unwrap();
+
+        let layout = info.to_layout(Some("LAYOUT"), &keys).unwrap();
+
+        assert_eq!(layout.layout.len(), 2);
+        assert_eq!(layout.layout[0].label, "ENTER");
+        assert_eq!(layout.layout[0].aliases, vec!["KPENTER"]);
+        assert_eq!(
+            layout.position_of("KPENTER").map(|p| (p.row, p.col)),
+            Some((1, 1))
+        );
+    }
+
+    #[test]
+    fn test_layout_selection() {
+        let info: QmkInfo = serde_json::from_str(INFO).unwrap();
+        let keys: KeyIndex = serde_json::from_str(r#"["ESC", null, "SPACE", "ENTER"]"#).unwrap();
+
+        assert!(info.find_layout(None).is_err());
+        assert!(info.find_layout(Some("LAYOUT_missing")).is_err());
+        assert!(info.to_layout(Some("LAYOUT_all"), &keys).is_err());
+        assert!(info
+            .to_layout(Some("LAYOUT_small"), &KeyIndex::List(vec![]))
+            .is_ok());
+    }
+}
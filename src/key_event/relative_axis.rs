@@ -0,0 +1,57 @@
+use strum_macros::{AsRefStr, EnumString, FromRepr};
+
+/*
+ * Relative axes
+ */
+
+pub const REL_MAX: u16 = 0x0f;
+
+#[allow(clippy::upper_case_acronyms)]
+#[allow(non_camel_case_types)]
+#[derive(
+    FromRepr,
+    AsRefStr,
+    EnumString,
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[strum(ascii_case_insensitive)]
+#[repr(u16)]
+pub enum RelativeAxis {
+    REL_X = 0x00,
+    REL_Y = 0x01,
+    REL_Z = 0x02,
+    REL_RX = 0x03,
+    REL_RY = 0x04,
+    REL_RZ = 0x05,
+    REL_HWHEEL = 0x06,
+    REL_DIAL = 0x07,
+    REL_WHEEL = 0x08,
+    REL_MISC = 0x09,
+    REL_RESERVED = 0x0a, /* Reserved to keep the high resolution wheels apart */
+    REL_WHEEL_HI_RES = 0x0b,
+    REL_HWHEEL_HI_RES = 0x0c,
+}
+
+impl RelativeAxis {
+    /// The detent-based axis a high resolution wheel reports alongside
+    pub fn low_res(self) -> Self {
+        match self {
+            Self::REL_WHEEL_HI_RES => Self::REL_WHEEL,
+            Self::REL_HWHEEL_HI_RES => Self::REL_HWHEEL,
+            axis => axis,
+        }
+    }
+}
+
+impl From<RelativeAxis> for u16 {
+    fn from(value: RelativeAxis) -> u16 {
+        value as u16
+    }
+}
@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::key_event::matix_mapper::{Layout, MatrixPosition};
+use crate::keyboard::{Operation, Result};
+
+/// A key referenced by a macro, either by its label in the layout or directly
+/// by its position in the matrix.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MacroKey {
+    Position(MatrixPosition),
+    Label(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MacroStep {
+    Press(MacroKey),
+    Release(MacroKey),
+    Tap(MacroKey),
+    Layer(u8),
+    /// Delay in milliseconds
+    Delay(u64),
+}
+
+/// A macro file looks like:
+///
+/// ```json
+/// { "steps": [{ "press": "LEFTCTRL" }, { "tap": [2, 3] }, { "delay": 50 }, { "release": "LEFTCTRL" }] }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Macro {
+    pub steps: Vec<MacroStep>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MacroAction {
+    Send(Operation),
+    Delay(Duration),
+}
+
+impl MacroKey {
+    fn resolve(&self, layout: Option<&Layout>) -> Result<MatrixPosition> {
+        match self {
+            Self::Position(position) => Ok(*position),
+            Self::Label(label) => layout
+                .ok_or_else(|| anyhow!("macro references label {label} but no layout was given"))?
+                .position_of(label)
+                .ok_or_else(|| anyhow!("label {label} is not present in the layout")),
+        }
+    }
+}
+
+impl Macro {
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Resolves every step into the operations that will be sent to the keyboard.
+    /// Positions still pressed at the end of the macro are released.
+    pub fn actions(&self, layout: Option<&Layout>) -> Result<Vec<MacroAction>> {
+        let mut held: Vec<MatrixPosition> = Vec::new();
+        let mut actions = Vec::new();
+
+        for step in &self.steps {
+            match step {
+                MacroStep::Press(key) => {
+                    let pos = key.resolve(layout)?;
+                    held.push(pos);
+                    actions.push(MacroAction::Send(Operation::UpdateMatrix(
+                        true, pos.row, pos.col,
+                    )));
+                }
+                MacroStep::Release(key) => {
+                    let pos = key.resolve(layout)?;
+                    held.retain(|p| *p != pos);
+                    actions.push(MacroAction::Send(Operation::UpdateMatrix(
+                        false, pos.row, pos.col,
+                    )));
+                }
+                MacroStep::Tap(key) => {
+                    let pos = key.resolve(layout)?;
+                    actions.push(MacroAction::Send(Operation::UpdateMatrix(
+                        true, pos.row, pos.col,
+                    )));
+                    actions.push(MacroAction::Send(Operation::UpdateMatrix(
+                        false, pos.row, pos.col,
+                    )));
+                }
+                MacroStep::Layer(layer) => {
+                    actions.push(MacroAction::Send(Operation::ChangeLayer(*layer)))
+                }
+                MacroStep::Delay(ms) => {
+                    actions.push(MacroAction::Delay(Duration::from_millis(*ms)))
+                }
+            }
+        }
+
+        actions.extend(
+            held.into_iter()
+                .map(|pos| MacroAction::Send(Operation::UpdateMatrix(false, pos.row, pos.col))),
+        );
+
+        Ok(actions)
+    }
+
+    /// Plays the macro, handing each operation to `send`. Delays are slept on the
+    /// calling thread so `send` should not hold any lock across calls.
+    pub fn run<F>(&self, layout: Option<&Layout>, send: F) -> Result<()>
+    where
+        F: FnMut(Operation) -> Result<()>,
+    {
+        Self::play(self.actions(layout)?, send)
+    }
+
+    pub fn play<F>(actions: Vec<MacroAction>, mut send: F) -> Result<()>
+    where
+        F: FnMut(Operation) -> Result<()>,
+    {
+        for action in actions {
+            match action {
+                MacroAction::Send(operation) => send(operation)?,
+                MacroAction::Delay(duration) => std::thread::sleep(duration),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_event::matix_mapper::{Layout, LayoutItem, MatrixPosition};
+    use crate::key_macro::{Macro, MacroAction};
+    use crate::keyboard::Operation;
+
+    fn layout() -> Layout {
+        Layout {
+            layout: vec![LayoutItem {
+                matrix: MatrixPosition { row: 1, col: 2 },
+                x: 2.0,
+                y: 1.0,
+                label: "ESC".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_and_resolve() {
+        let m: Macro = serde_json::from_str(
+            r#"{ "steps": [{ "press": "esc" }, { "delay": 10 }, { "tap": [3, 4] }, { "layer": 2 }] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            m.actions(Some(&layout())).unwrap(),
+            vec![
+                MacroAction::Send(Operation::UpdateMatrix(true, 1, 2)),
+                MacroAction::Delay(std::time::Duration::from_millis(10)),
+                MacroAction::Send(Operation::UpdateMatrix(true, 3, 4)),
+                MacroAction::Send(Operation::UpdateMatrix(false, 3, 4)),
+                MacroAction::Send(Operation::ChangeLayer(2)),
+                // ESC was never released by the macro itself
+                MacroAction::Send(Operation::UpdateMatrix(false, 1, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_label() {
+        let m: Macro = serde_json::from_str(r#"{ "steps": [{ "tap": "F13" }] }"#).unwrap();
+
+        assert!(m.actions(Some(&layout())).is_err());
+        assert!(m.actions(None).is_err());
+    }
+}
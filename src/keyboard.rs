@@ -1,10 +1,23 @@
-use anyhow::anyhow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
 use hidapi::HidApi;
-use log::{debug, trace};
+use log::{debug, trace, warn};
 
-const REPORT_LENGTH: usize = 32;
+use crate::metrics::Metrics;
 
-#[derive(Debug)]
+pub const REPORT_LENGTH: usize = 32;
+
+/// How long the controller has to answer an operation
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// First byte of the keyboard reports the controller sends back in host
+/// report mode
+pub const HOST_REPORT_ID: u8 = 0x48;
+
+#[derive(Debug, Clone)]
 pub struct HidInfo {
     pub vendor_id: u16,
     pub product_id: u16,
@@ -43,18 +56,29 @@ impl From<u8> for Layers {
     }
 }
 
-impl ToString for Layers {
-    fn to_string(&self) -> String {
-        format!("{:?}", self)
+impl std::fmt::Display for Layers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operation {
     Bootloader,
     GetLayer,
     ChangeLayer(u8),
     UpdateMatrix(bool, u8, u8),
+    /// Turns the encoder with this index by one step
+    EncoderTurn(u8, EncoderDirection),
+    /// Has the controller send its keyboard reports back over raw HID instead
+    /// of acting as a USB keyboard, or go back to acting as one
+    HostReports(bool),
 }
 
 impl Operation {
@@ -77,6 +101,19 @@ impl Operation {
                 ret[2] = *row;
                 ret[3] = *col;
             }
+            Self::EncoderTurn(index, direction) => {
+                ret[0] = 0x46;
+                ret[1] = *index;
+                ret[2] = if *direction == EncoderDirection::Clockwise {
+                    1
+                } else {
+                    0
+                };
+            }
+            Self::HostReports(enabled) => {
+                ret[0] = 0x47;
+                ret[1] = if *enabled { 1 } else { 0 };
+            }
         }
         ret
     }
@@ -99,20 +136,30 @@ impl KeyboardResponse {
 
 pub struct Keyboard {
     device: hidapi::HidDevice,
+    hid_info: HidInfo,
 }
 
 pub type Result<T> = std::result::Result<T, anyhow::Error>;
 
-trait TransposableResult<T, U> {
-    fn transpose(self) -> std::result::Result<U, T>;
+/// Carries out operations, either the controller itself or something acting
+/// like it
+pub trait Backend: Send {
+    fn send_message(&mut self, operation: Operation) -> Result<KeyboardResponse>;
+
+    /// Connects to the controller again after an operation failed
+    fn reconnect(&mut self) -> Result<()> {
+        bail!("this backend cannot reconnect")
+    }
 }
 
-impl<T, U> TransposableResult<T, U> for std::result::Result<T, U> {
-    fn transpose(self) -> std::result::Result<U, T> {
-        match self {
-            Ok(o) => Err(o),
-            Err(e) => Ok(e),
-        }
+impl Backend for Keyboard {
+    fn send_message(&mut self, operation: Operation) -> Result<KeyboardResponse> {
+        Keyboard::send_message(self, operation)
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        *self = Keyboard::new(&self.hid_info)?;
+        Ok(())
     }
 }
 
@@ -128,19 +175,27 @@ impl Keyboard {
                             && device.usage_page() == hid_info.usage_page
                             && device.usage() == hid_info.usage
                     })
-                    .expect("Unable to find expected device");
+                    .ok_or_else(|| anyhow!("Unable to find expected device {:04x?}", hid_info))?;
 
                 let macropad = api
                     .open_path(device.path())
-                    .expect("Could not open HID device");
+                    .map_err(|e| anyhow!("Could not open HID device: {e}"))?;
 
-                Ok(Keyboard { device: macropad })
+                Ok(Keyboard {
+                    device: macropad,
+                    hid_info: hid_info.clone(),
+                })
             }
             Err(e) => Err(anyhow!(e)),
         }
     }
 
     pub fn send_message(&self, operation: crate::Operation) -> Result<KeyboardResponse> {
+        self.write(operation)?;
+        self.read_response()
+    }
+
+    pub fn write(&self, operation: Operation) -> Result<()> {
         let mut buffer = [0u8; REPORT_LENGTH + 1];
 
         buffer[1..].copy_from_slice(&operation.report());
@@ -148,31 +203,167 @@ impl Keyboard {
         debug!("Writing: {:?}", operation);
         trace!("Writing: {:02x?}", buffer);
 
-        let _wrote = self
-            .device
+        self.device
             .write(&buffer)
-            .expect("Could not write to HID device");
-
-        let mut resp_buf = [0u8; REPORT_LENGTH];
-
-        let response = self
-            .device
-            .read_timeout(&mut resp_buf, 10000)
-            .map(|_| ())
-            .transpose()
-            .and_then(|e| {
-                if e.to_string().contains("device disconnected") {
-                    Err(())
+            .map_err(|e| anyhow!("Could not write to HID device: {e}"))?;
+
+        Ok(())
+    }
+
+    /// Waits for the response to the last operation written, for up to
+    /// `RESPONSE_TIMEOUT`
+    pub fn read_response(&self) -> Result<KeyboardResponse> {
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+
+        loop {
+            let mut resp_buf = [0u8; REPORT_LENGTH];
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            let read = match self
+                .device
+                .read_timeout(&mut resp_buf, remaining.as_millis() as i32)
+            {
+                Ok(read) => read,
+                // The controller goes away when asked to jump to its bootloader
+                Err(e) if e.to_string().contains("device disconnected") => {
+                    return Ok(KeyboardResponse::None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if read == 0 {
+                bail!(
+                    "the controller did not answer within {:?}",
+                    RESPONSE_TIMEOUT
+                );
+            }
+
+            // Keyboard reports sent back to the host are read on their own
+            // handle, see `read_report`
+            if resp_buf[0] == HOST_REPORT_ID {
+                continue;
+            }
+
+            let response = KeyboardResponse::parse_response(resp_buf);
+            debug!("Response: {:?}", response);
+            trace!("Response: {:02x?}", resp_buf);
+
+            return Ok(response);
+        }
+    }
+
+    /// Waits for the next report the controller sends on its own
+    pub fn read_report(&self) -> Result<[u8; REPORT_LENGTH]> {
+        let mut buffer = [0u8; REPORT_LENGTH];
+
+        self.device
+            .read(&mut buffer)
+            .map_err(|e| anyhow!("Could not read from HID device: {e}"))?;
+        trace!("Read: {:02x?}", buffer);
+
+        Ok(buffer)
+    }
+}
+
+/// Keyboard shared between the forwarding loop and other sources of matrix
+/// updates, like macros. Positions are reference counted so a position held by
+/// one source is only released once every source let go of it.
+pub struct SharedKeyboard {
+    keyboard: Box<dyn Backend>,
+    held: HashMap<(u8, u8), usize>,
+    metrics: Arc<Metrics>,
+    /// Layer last reported by the backend. Layer keys of the firmware change
+    /// it without telling.
+    layer: Option<u8>,
+}
+
+impl SharedKeyboard {
+    pub fn new(keyboard: Box<dyn Backend>) -> Self {
+        Self {
+            keyboard,
+            held: HashMap::new(),
+            metrics: Arc::default(),
+            layer: None,
+        }
+    }
+
+    pub fn layer(&self) -> Option<u8> {
+        self.layer
+    }
+
+    /// Counts of the operations sent to the controller and their latencies
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Sends `operation` to the backend, recording it in the metrics. If it
+    /// fails, the backend is reconnected once and the operation sent again,
+    /// so the controller being unplugged and plugged back in is survived.
+    fn forward(&mut self, operation: Operation) -> Result<KeyboardResponse> {
+        let start = Instant::now();
+        let mut response = self.keyboard.send_message(operation);
+        self.metrics.response(start.elapsed(), response.is_ok());
+
+        if let Err(e) = &response {
+            if self.keyboard.reconnect().is_ok() {
+                warn!("Reconnected to the controller after: {:?}", e);
+                self.metrics.reconnect();
+
+                let start = Instant::now();
+                response = self.keyboard.send_message(operation);
+                self.metrics.response(start.elapsed(), response.is_ok());
+            }
+        }
+
+        if let Ok(KeyboardResponse::CurrentLayer(layer)) = &response {
+            self.layer = Some(*layer);
+        }
+
+        match operation {
+            Operation::UpdateMatrix(pressed, ..) => self.metrics.matrix_update(pressed),
+            Operation::EncoderTurn(..) => self.metrics.encoder_turn(),
+            Operation::ChangeLayer(_) => self.metrics.layer_switch(),
+            _ => {}
+        }
+
+        response
+    }
+
+    pub fn send_message(&mut self, operation: Operation) -> Result<KeyboardResponse> {
+        match operation {
+            Operation::UpdateMatrix(pressed, row, col) => {
+                let count = self.held.entry((row, col)).or_default();
+                let changed = if pressed {
+                    *count += 1;
+                    *count == 1
+                } else {
+                    let was_held = *count > 0;
+                    *count = count.saturating_sub(1);
+                    was_held && *count == 0
+                };
+
+                if changed {
+                    self.forward(operation)
                 } else {
-                    Ok(e)
+                    Ok(KeyboardResponse::None)
                 }
-            })
-            .transpose()
-            .map(|_| KeyboardResponse::parse_response(resp_buf))?;
+            }
+            _ => self.forward(operation),
+        }
+    }
 
-        debug!("Response: {:?}", response);
-        trace!("Response: {:02x?}", resp_buf);
+    /// Releases every position that is still held, regardless of its source
+    pub fn release_all(&mut self) -> Result<()> {
+        let held: Vec<(u8, u8)> = self
+            .held
+            .drain()
+            .filter(|(_, c)| *c > 0)
+            .map(|(p, _)| p)
+            .collect();
+
+        for (row, col) in held {
+            self.forward(Operation::UpdateMatrix(false, row, col))?;
+        }
 
-        Ok(response)
+        Ok(())
     }
 }
@@ -1,22 +1,52 @@
+mod bench;
+mod config;
+mod daemon;
 mod event_input_device;
+mod host_reports;
+mod i3;
+mod ipc;
 mod key_event;
+mod key_macro;
 mod keyboard;
+mod matrix_map_editor;
+mod metrics;
+mod pipeline;
+mod software_keyboard;
+mod stats;
+#[cfg(test)]
+mod test_fixtures;
 mod uinput;
 
-use std::convert::Into;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::Parser;
 use clap_num::maybe_hex;
-use log::error;
+use log::{error, warn};
 
 use key_event::KeyEvent;
 
+use crate::key_event::builtin_layouts;
 use crate::key_event::key_code::KeyCode;
-use crate::key_event::matix_mapper::{Layout, LayoutItem, MatrixPosition};
-
-use self::event_input_device::EventDevice;
-use self::keyboard::{HidInfo, Keyboard, KeyboardResponse, Operation};
+use crate::key_event::kle;
+use crate::key_event::layout_diagram::{self, Labels};
+use crate::key_event::layout_validation::{self, Severity, ValidationOptions};
+use crate::key_event::matix_mapper::{Layout, MatrixMapper, MatrixPosition};
+use crate::key_event::qmk_info::{KeyIndex, QmkInfo};
+
+use self::config::{Config, SoftwareConfig, StageConfig};
+use self::daemon::{Daemon, Sources};
+use self::event_input_device::{EventDevice, Mouse};
+use self::host_reports::HostReports;
+use self::ipc::{IpcRequest, IpcResponse};
+use self::key_macro::Macro;
+use self::keyboard::{Backend, HidInfo, Keyboard, KeyboardResponse, Operation, SharedKeyboard};
+use self::matrix_map_editor::{AlternateScreen, EditorAction, MatrixMapEditor};
+use self::software_keyboard::{Keymap, SoftwareBackend, SoftwareKeyboard, DEFAULT_TAPPING_TERM};
+use self::stats::{KeyStats, Stats};
+use self::uinput::Identity;
 
 const VENDOR_ID: u16 = 0x4b41; // Kasama (unofficial)
                                // const PRODUCT_ID: u16 = 0x564b; // Virtual Keyboard
@@ -38,28 +68,153 @@ struct App {
     /// Suppress all output when set
     quiet: bool,
 
-    #[arg(long, default_value_t = VENDOR_ID, value_parser=maybe_hex::<u16>)]
-    /// HID Vendor ID
-    vid: u16,
-    #[arg(long, default_value_t = PRODUCT_ID, value_parser=maybe_hex::<u16>)]
-    /// HID Product ID
-    pid: u16,
-    #[arg(short = 'p', long, default_value_t = USAGE_PAGE, value_parser=maybe_hex::<u16>)]
-    /// HID Usage Page
-    usage_page: u16,
-    #[arg(short, long, default_value_t = USAGE, value_parser=maybe_hex::<u16>)]
-    /// HID Usage
-    usage: u16,
+    #[arg(short, long)]
+    /// Configuration file. Defaults to $XDG_CONFIG_HOME/qmk-virtual-keyboard/config.toml
+    config: Option<PathBuf>,
+
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    /// HID Vendor ID [default: 0x4b41]
+    vid: Option<u16>,
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    /// HID Product ID [default: 0x504d]
+    pid: Option<u16>,
+    #[arg(short = 'p', long, value_parser=maybe_hex::<u16>)]
+    /// HID Usage Page [default: 0xff60]
+    usage_page: Option<u16>,
+    #[arg(short, long, value_parser=maybe_hex::<u16>)]
+    /// HID Usage [default: 0x61]
+    usage: Option<u16>,
+
+    #[arg(skip)]
+    settings: Config,
 }
 
 #[derive(clap::Subcommand, Debug)]
 enum Commands {
     PrintKeyboardLayer,
     KeyboardBootloader,
-    ChangeKeyboardLayer { layer: u8 },
-    SendKey { row: u8, col: u8 },
-    VirtualKeyboard { device: String, config: String },
-    GenerateMatrixMap { device: String, rows: u8, cols: u8 },
+    ChangeKeyboardLayer {
+        layer: u8,
+    },
+    SendKey {
+        row: u8,
+        col: u8,
+    },
+    VirtualKeyboard {
+        /// Input device, overrides the devices in the config file
+        device: Option<String>,
+        /// Layout file or built-in layout (ansi104, iso105, tkl, 60%, laptop),
+        /// overrides the layouts in the config file
+        layout: Option<String>,
+    },
+    /// Maps keys of the input device to matrix positions interactively, using
+    /// the device itself to move around the grid
+    GenerateMatrixMap {
+        device: String,
+        rows: u8,
+        cols: u8,
+        #[arg(short, long)]
+        /// Layout file or built-in layout to start from
+        layout: Option<String>,
+        #[arg(short, long)]
+        /// File the layout is saved to, it is printed on exit otherwise
+        output: Option<PathBuf>,
+    },
+    RunMacro {
+        file: PathBuf,
+        #[arg(short, long)]
+        /// Layout file or built-in layout used to resolve key labels referenced
+        /// by the macro
+        layout: Option<String>,
+        #[arg(short, long)]
+        /// Run the macro through a running virtual-keyboard daemon instead of
+        /// connecting to the keyboard directly
+        daemon: bool,
+    },
+    /// Validates the configuration file and everything it references
+    CheckConfig,
+    /// Converts a layout macro from a QMK info.json or keyboard.json to a layout
+    ImportQmkLayout {
+        info: PathBuf,
+        /// Evdev key driving each key of the layout macro, in layout order
+        keys: PathBuf,
+        #[arg(short, long)]
+        /// Layout macro name, required when the keyboard has more than one
+        layout: Option<String>,
+    },
+    /// Converts keyboard-layout-editor.com raw data to a layout. Keys take their
+    /// matrix position from a "row,col" legend and their label from the others
+    ImportKleLayout {
+        file: PathBuf,
+    },
+    /// Checks a layout for unknown or duplicate keys, colliding matrix positions,
+    /// positions outside of the firmware matrix and keys of the input device
+    /// missing from the layout
+    ValidateLayout {
+        /// Layout file or built-in layout, defaults to the layouts in the config
+        /// file
+        layout: Option<String>,
+        #[arg(short, long)]
+        /// Input device whose keys should all be in the layout
+        device: Option<String>,
+        #[arg(long)]
+        /// Matrix rows of the firmware
+        rows: Option<u8>,
+        #[arg(long)]
+        /// Matrix columns of the firmware
+        cols: Option<u8>,
+    },
+    /// Measures HID round trips to the controller and, with a device, how long
+    /// its key presses take to reach the controller, reporting percentiles
+    Bench {
+        #[arg(short = 'n', long, default_value_t = 100)]
+        /// Number of round trips, and of key presses timed on the device
+        count: usize,
+        #[arg(short, long)]
+        /// Input device whose key presses are forwarded and timed, it is
+        /// grabbed meanwhile
+        device: Option<String>,
+        #[arg(short, long)]
+        /// Evdev device the controller types on, to time key presses until
+        /// their keystroke
+        output: Option<String>,
+        #[arg(short, long)]
+        /// Layout file or built-in layout, defaults to the layouts in the config
+        /// file
+        layout: Option<String>,
+    },
+    /// Draws a layout as a keyboard diagram
+    ShowLayout {
+        /// Layout file or built-in layout, defaults to the layouts in the config
+        /// file
+        layout: Option<String>,
+        #[arg(short = 'L', long, default_value = "keys")]
+        /// What to write on each key: keys, matrix or both
+        labels: Labels,
+        #[arg(short, long)]
+        /// Input device whose pressed keys are highlighted live, it is not
+        /// grabbed
+        device: Option<String>,
+    },
+    /// Draws a heatmap of the key presses recorded by the daemon over the
+    /// layout, followed by the most pressed keys
+    Stats {
+        /// Layout file or built-in layout, defaults to the layouts in the config
+        /// file
+        layout: Option<String>,
+        #[arg(short = 'L', long, default_value = "keys")]
+        /// What to write on each key: keys, matrix or both
+        labels: Labels,
+        #[arg(short, long)]
+        /// Only counts presses made on this layer
+        layer: Option<u8>,
+        #[arg(short, long)]
+        /// Statistics file, defaults to the one in the config file
+        file: Option<PathBuf>,
+        #[arg(short = 'n', long, default_value_t = 10)]
+        /// Number of keys listed
+        top: usize,
+    },
 }
 
 fn print_error<T, E: std::fmt::Debug>(r: Result<T, E>) {
@@ -68,7 +223,7 @@ fn print_error<T, E: std::fmt::Debug>(r: Result<T, E>) {
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), anyhow::Error> {
-    let app = App::parse();
+    let mut app = App::parse();
 
     if !app.quiet {
         simple_logger::init_with_level(match app.verbose {
@@ -79,6 +234,10 @@ async fn main() -> Result<(), anyhow::Error> {
         })?;
     }
 
+    if !matches!(app.command, Commands::CheckConfig) {
+        app.settings = Config::load(app.config.as_deref())?;
+    }
+
     match app.command {
         Commands::PrintKeyboardLayer => print_error(app.print_keyboard_layer()),
         Commands::KeyboardBootloader => print_error(app.keyboard_bootloader()),
@@ -86,68 +245,186 @@ async fn main() -> Result<(), anyhow::Error> {
         Commands::SendKey { row, col } => print_error(app.tap_key(row, col)),
         Commands::VirtualKeyboard {
             ref device,
-            ref config,
-        } => print_error(app.virtual_keyboard(device, config)),
+            ref layout,
+        } => print_error(app.virtual_keyboard(device.as_deref(), layout.as_deref())),
         Commands::GenerateMatrixMap {
             ref device,
             rows,
             cols,
-        } => print_error(app.generate_matrix_map(device, rows, cols)),
+            ref layout,
+            ref output,
+        } => print_error(app.generate_matrix_map(
+            device,
+            rows,
+            cols,
+            layout.as_deref(),
+            output.as_deref(),
+        )),
+        Commands::RunMacro {
+            ref file,
+            ref layout,
+            daemon,
+        } => print_error(app.run_macro(file, layout.as_deref(), daemon)),
+        // Fails the process so scripts can tell a broken config apart
+        Commands::CheckConfig => app.check_config()?,
+        Commands::ImportQmkLayout {
+            ref info,
+            ref keys,
+            ref layout,
+        } => print_error(app.import_qmk_layout(info, keys, layout.as_deref())),
+        Commands::ImportKleLayout { ref file } => print_error(app.import_kle_layout(file)),
+        Commands::ValidateLayout {
+            ref layout,
+            ref device,
+            rows,
+            cols,
+        } => app.validate_layout(layout.as_deref(), device.as_deref(), rows, cols)?,
+        Commands::ShowLayout {
+            ref layout,
+            labels,
+            ref device,
+        } => print_error(app.show_layout(layout.as_deref(), labels, device.as_deref())),
+        Commands::Stats {
+            ref layout,
+            labels,
+            layer,
+            ref file,
+            top,
+        } => print_error(app.show_stats(layout.as_deref(), labels, layer, file.as_deref(), top)),
+        Commands::Bench {
+            count,
+            ref device,
+            ref output,
+            ref layout,
+        } => print_error(app.bench(
+            count,
+            device.as_deref(),
+            output.as_deref(),
+            layout.as_deref(),
+        )),
     };
 
     Ok(())
 }
 
 impl App {
-    fn connect_to_keyboard(&self) -> Result<Keyboard, anyhow::Error> {
-        Keyboard::new(&HidInfo {
-            vendor_id: self.vid,
-            product_id: self.pid,
-            usage_page: self.usage_page,
-            usage: self.usage,
-        })
+    fn hid_info(&self) -> HidInfo {
+        let controller = &self.settings.controller;
+
+        HidInfo {
+            vendor_id: self.vid.or(controller.vendor_id).unwrap_or(VENDOR_ID),
+            product_id: self.pid.or(controller.product_id).unwrap_or(PRODUCT_ID),
+            usage_page: self
+                .usage_page
+                .or(controller.usage_page)
+                .unwrap_or(USAGE_PAGE),
+            usage: self.usage.or(controller.usage).unwrap_or(USAGE),
+        }
     }
 
-    fn virtual_keyboard(&self, device: &str, matrix_config_file: &str) -> anyhow::Result<()> {
-        let device = EventDevice::from_path(device)?;
-        let keyboard = self.connect_to_keyboard()?;
+    fn connect_to_keyboard(&self) -> Result<Keyboard, anyhow::Error> {
+        Keyboard::new(&self.hid_info())
+    }
 
-        let matrix_file_contents = std::fs::read_to_string(matrix_config_file)?;
-        let matrix: Layout = serde_json::from_str(&matrix_file_contents)?;
+    fn load_layout(&self, layout_file: Option<&str>) -> anyhow::Result<Layout> {
+        match layout_file {
+            Some(name) => Layout::from_name_or_file(name),
+            None => self.settings.load_layouts(),
+        }
+    }
 
-        let mut matrix_mapper: [MatrixPosition; u8::MAX as usize] =
-            [(0, 0).into(); u8::MAX as usize];
+    fn software_keyboard(
+        &self,
+        software: &SoftwareConfig,
+        layout_file: Option<&str>,
+    ) -> anyhow::Result<SoftwareKeyboard> {
+        let keymap = Keymap::from_file(&software.keymap)
+            .map_err(|e| anyhow::anyhow!("invalid keymap {}: {e}", software.keymap.display()))?;
+        let tapping_term =
+            Duration::from_millis(software.tapping_term.unwrap_or(DEFAULT_TAPPING_TERM));
+
+        SoftwareKeyboard::new(&keymap, &self.load_layout(layout_file)?, tapping_term)
+    }
 
-        matrix.layout.iter().for_each(|layout_item| {
-            let keycode: Result<KeyCode, _> = layout_item.label.parse();
-            if let Ok(keycode) = keycode {
-                matrix_mapper[Into::<usize>::into(keycode)] = layout_item.matrix
-            };
-        });
+    fn virtual_keyboard(
+        &self,
+        device: Option<&str>,
+        layout_file: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let device_paths = match device {
+            Some(device) => vec![device.to_string()],
+            None => self.settings.devices.clone(),
+        };
+        if device_paths.is_empty() && self.settings.mice.is_empty() {
+            anyhow::bail!("No input device given and none configured");
+        }
 
-        loop {
-            let event = device.next()?;
-
-            if let KeyEvent::Press(code, held, _) = event {
-                if !held && code != KeyCode::NONE {
-                    let matrix_pos = matrix_mapper[code as usize];
-                    let _response = keyboard.send_message(Operation::UpdateMatrix(
-                        true,
-                        matrix_pos.row,
-                        matrix_pos.col,
-                    ))?;
-                }
+        let devices = device_paths
+            .iter()
+            .map(EventDevice::from_path)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let identity = match (self.settings.clone_identity, devices.first()) {
+            (true, Some(device)) => Identity::of(device)?,
+            (true, None) => {
+                warn!("No keyboard to clone the identity of");
+                Identity::default()
             }
+            (false, _) => Identity::default(),
+        };
 
-            if let KeyEvent::Release(code, _) = event {
-                let matrix_pos = matrix_mapper[code as usize];
-                let _response = keyboard.send_message(Operation::UpdateMatrix(
-                    false,
-                    matrix_pos.row,
-                    matrix_pos.col,
-                ))?;
+        let backend: Box<dyn Backend> = match &self.settings.software {
+            Some(software) => Box::new(SoftwareBackend::new(
+                self.software_keyboard(software, layout_file)?,
+                &identity,
+            )?),
+            None => Box::new(self.connect_to_keyboard()?),
+        };
+
+        let daemon = Daemon::new(
+            SharedKeyboard::new(backend),
+            Sources {
+                config: self.config.clone(),
+                layout: layout_file.map(String::from),
+            },
+            identity.clone(),
+        )?;
+
+        let mice = self
+            .settings
+            .mice
+            .iter()
+            .map(Mouse::open)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Kept until the daemon stops, the controller gets its keyboard back then
+        let _host_reports = match self.settings.software {
+            None if self.settings.controller.host_reports => {
+                Some(HostReports::start(&self.hid_info(), &identity)?)
             }
+            _ => None,
+        };
+
+        daemon.run(devices, mice)
+    }
+
+    fn run_macro(&self, file: &Path, layout: Option<&str>, daemon: bool) -> anyhow::Result<()> {
+        if daemon {
+            let request = IpcRequest::RunMacro {
+                steps: Macro::from_file(file)?.steps,
+            };
+
+            return match ipc::request(&ipc::socket_path()?, &request)? {
+                IpcResponse::Ok => Ok(()),
+                IpcResponse::Error { message } => Err(anyhow::anyhow!(message)),
+            };
         }
+
+        let layout = self.load_layout(layout)?;
+        let keyboard = self.connect_to_keyboard()?;
+
+        Macro::from_file(file)?.run(Some(&layout), |operation| {
+            keyboard.send_message(operation).map(|_| ())
+        })
     }
 
     fn tap_key(&self, _row: u8, _col: u8) -> Result<(), anyhow::Error> {
@@ -177,7 +454,10 @@ impl App {
         let response = keyboard.send_message(Operation::GetLayer)?;
 
         if let KeyboardResponse::CurrentLayer(layer) = response {
-            println!("⌨: {}", keyboard::Layers::from(layer).to_string());
+            match self.settings.layer_name(layer) {
+                Some(name) => println!("⌨: {}", name),
+                None => println!("⌨: {}", keyboard::Layers::from(layer)),
+            }
         }
 
         Ok(())
@@ -195,6 +475,38 @@ impl App {
         Ok(())
     }
 
+    fn bench(
+        &self,
+        count: usize,
+        device: Option<&str>,
+        output: Option<&str>,
+        layout: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        if output.is_some() && device.is_none() {
+            anyhow::bail!("--output needs a --device to press keys on");
+        }
+
+        let keyboard = self.connect_to_keyboard()?;
+        let mut samples = bench::round_trips(&keyboard, count)?;
+
+        if let Some(device) = device {
+            let mapper = MatrixMapper::new(&self.load_layout(layout)?);
+            let output = output.map(EventDevice::from_path_shared).transpose()?;
+            let device = EventDevice::from_path(device)?;
+
+            eprintln!("Press {count} keys on the device");
+            samples.extend(bench::forwarding(
+                &keyboard, &device, &mapper, output, count,
+            )?);
+        }
+
+        for samples in samples {
+            println!("{samples}");
+        }
+
+        Ok(())
+    }
+
     fn keyboard_bootloader(&self) -> Result<(), anyhow::Error> {
         let keyboard = self.connect_to_keyboard()?;
 
@@ -203,60 +515,369 @@ impl App {
         Ok(())
     }
 
-    fn generate_matrix_map(&self, device: &str, rows: u8, cols: u8) -> Result<(), anyhow::Error> {
+    fn generate_matrix_map(
+        &self,
+        device: &str,
+        rows: u8,
+        cols: u8,
+        layout: Option<&str>,
+        output: Option<&Path>,
+    ) -> Result<(), anyhow::Error> {
+        if rows == 0 || cols == 0 {
+            anyhow::bail!("The matrix needs at least one row and one column");
+        }
+
+        let layout = layout.map(Layout::from_name_or_file).transpose()?;
+        let mut editor = MatrixMapEditor::new(rows, cols, layout);
         let device = EventDevice::from_path(device)?;
+        let screen = AlternateScreen::enter()?;
 
-        eprintln!("Press one button on the keyboard at a time from left to right and top to bottom to generate the matrix map. Press Ctrl+C to exit.");
-        eprintln!("The first will be position (0, 0). Press that button again on any position to skip it if there is no button there.\n");
-
-        let mut skip = KeyCode::NONE;
-
-        let result_matrix: Vec<LayoutItem> = (0..rows)
-            .map(|r| {
-                (0..cols)
-                    .map(|c| {
-                        eprint!("Press {r},{c}: ");
-                        std::io::stderr().flush()?;
-                        let code = loop {
-                            let event = device.next()?;
-
-                            if let KeyEvent::Press(code, false, _) = event {
-                                if code == skip {
-                                    break KeyCode::NONE;
-                                }
-
-                                if r == 0 && c == 0 {
-                                    skip = code;
-                                }
-
-                                break code;
-                            }
-                        };
-
-                        eprintln!("{:?}", code);
-                        match code {
-                            KeyCode::NONE => Ok(None),
-                            _ => Ok(Some(LayoutItem {
-                                matrix: MatrixPosition { row: r, col: c },
-                                x: c,
-                                y: r,
-                                label: format!("{:?}", code),
-                            })),
-                        }
-                    })
-                    .collect::<anyhow::Result<Vec<_>>>()
-                    .map(|v| v.into_iter().flatten().collect::<Vec<_>>())
-            })
-            .collect::<anyhow::Result<Vec<_>>>()
-            .map(|v| v.into_iter().flatten().collect())?;
-
-        let layout = Layout {
-            layout: result_matrix,
-        };
+        loop {
+            editor.render(&mut std::io::stderr())?;
+
+            let KeyEvent::Press(code, false, _) = device.next()? else {
+                continue;
+            };
+
+            match editor.handle(code) {
+                EditorAction::Continue => {}
+                EditorAction::Save => match output {
+                    Some(output) => {
+                        let layout = editor.to_layout();
+                        std::fs::write(output, serde_json::to_string(&layout)?)?;
+                        editor.mark_saved(format!(
+                            "Saved {} keys to {}",
+                            layout.layout.len(),
+                            output.display()
+                        ));
+                    }
+                    None => editor
+                        .mark_saved("No output file, the layout is printed on exit".to_string()),
+                },
+                EditorAction::Quit => break,
+            }
+        }
+        drop(screen);
+
+        if output.is_none() {
+            let serialized_str = serde_json::to_string(&editor.to_layout())?;
+            println!("{serialized_str}");
+        }
+
+        Ok(())
+    }
+
+    fn import_qmk_layout(
+        &self,
+        info: &Path,
+        keys: &Path,
+        layout: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let layout = QmkInfo::from_file(info)?.to_layout(layout, &KeyIndex::from_file(keys)?)?;
 
         let serialized_str = serde_json::to_string(&layout)?;
         println!("{serialized_str}");
 
         Ok(())
     }
+
+    fn import_kle_layout(&self, file: &Path) -> Result<(), anyhow::Error> {
+        let layout = kle::load(file)?;
+
+        let serialized_str = serde_json::to_string(&layout)?;
+        println!("{serialized_str}");
+
+        Ok(())
+    }
+
+    /// Every key the given devices can send, if any device was given
+    fn device_keys(&self, devices: &[String]) -> anyhow::Result<Option<Vec<KeyCode>>> {
+        if devices.is_empty() {
+            return Ok(None);
+        }
+
+        let mut keys = Vec::new();
+        for device in devices {
+            keys.extend(EventDevice::from_path_shared(device)?.supported_keys()?);
+        }
+        keys.sort_by_key(|code| *code as u16);
+        keys.dedup();
+
+        Ok(Some(keys))
+    }
+
+    fn show_layout(
+        &self,
+        layout_file: Option<&str>,
+        labels: Labels,
+        device: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let layout = self.load_layout(layout_file)?;
+        let Some(device) = device else {
+            print!(
+                "{}",
+                layout_diagram::render(&layout, labels, &HashSet::new())
+            );
+            return Ok(());
+        };
+
+        let device = EventDevice::from_path_shared(device)?;
+        let mapper = MatrixMapper::new(&layout);
+        let mut held: HashMap<KeyCode, &[MatrixPosition]> = HashMap::new();
+
+        loop {
+            let pressed = held.values().flat_map(|p| p.iter().copied()).collect();
+            print!(
+                "\x1b[H\x1b[2J{}",
+                layout_diagram::render(&layout, labels, &pressed)
+            );
+            std::io::stdout().flush()?;
+
+            match device.next()? {
+                KeyEvent::Press(code, false, _) => {
+                    held.insert(code, mapper.positions(code));
+                }
+                KeyEvent::Release(code, _) => {
+                    held.remove(&code);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn show_stats(
+        &self,
+        layout_file: Option<&str>,
+        labels: Labels,
+        layer: Option<u8>,
+        file: Option<&Path>,
+        top: usize,
+    ) -> anyhow::Result<()> {
+        let file = file
+            .map(Path::to_path_buf)
+            .or_else(|| self.settings.stats.as_ref().map(|s| s.file.clone()))
+            .ok_or_else(|| anyhow::anyhow!("No statistics file given and none configured"))?;
+        let stats = Stats::from_file(&file)?;
+        let layout = self.load_layout(layout_file)?;
+
+        let presses = |key: &KeyStats| match layer {
+            Some(layer) => key.layers.get(&layer).copied().unwrap_or_default(),
+            None => key.presses,
+        };
+        let label = |pos: MatrixPosition| {
+            layout
+                .layout
+                .iter()
+                .find(|item| item.matrix == pos)
+                .map(|item| item.label.clone())
+                .unwrap_or_else(|| format!("{},{}", pos.row, pos.col))
+        };
+
+        let counts = stats
+            .keys()
+            .into_iter()
+            .map(|key| (key.matrix, presses(key)))
+            .collect();
+        print!(
+            "{}",
+            layout_diagram::render_heatmap(&layout, labels, &counts)
+        );
+
+        println!("\nPresses by layer:");
+        for (layer, count) in stats.layers() {
+            match self.settings.layer_name(layer) {
+                Some(name) => println!("  {layer} ({name}): {count}"),
+                None => println!("  {layer}: {count}"),
+            }
+        }
+
+        println!("\nMost pressed keys:");
+        let mut keys = stats.keys();
+        keys.sort_by_key(|key| std::cmp::Reverse(presses(key)));
+        for key in keys.iter().filter(|key| presses(key) > 0).take(top) {
+            let hold = key
+                .mean_hold_ms()
+                .map(|ms| format!(", held {ms}ms on average"))
+                .unwrap_or_default();
+            println!("  {}: {}{hold}", label(key.matrix), presses(key));
+        }
+
+        Ok(())
+    }
+
+    fn validate_layout(
+        &self,
+        layout_file: Option<&str>,
+        device: Option<&str>,
+        rows: Option<u8>,
+        cols: Option<u8>,
+    ) -> Result<(), anyhow::Error> {
+        let layout = self.load_layout(layout_file)?;
+        let devices = match device {
+            Some(device) => vec![device.to_string()],
+            None => self.settings.devices.clone(),
+        };
+
+        let options = ValidationOptions {
+            matrix_size: match (rows, cols) {
+                (Some(rows), Some(cols)) => Some((rows, cols)),
+                (None, None) => self.settings.matrix_size(),
+                _ => anyhow::bail!("Both --rows and --cols are needed to check the matrix size"),
+            },
+            device_keys: self.device_keys(&devices)?,
+        };
+
+        let diagnostics = layout_validation::validate(&layout, &options);
+        for diagnostic in &diagnostics {
+            println!("{diagnostic}");
+        }
+
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        if errors > 0 {
+            anyhow::bail!("{errors} error(s) found in the layout");
+        }
+
+        println!("Layout is valid ({} keys)", layout.layout.len());
+        Ok(())
+    }
+
+    fn check_config(&mut self) -> Result<(), anyhow::Error> {
+        let path = self.config.clone().or_else(Config::default_path);
+        match &path {
+            Some(path) => println!("Config file: {}", path.display()),
+            None => println!("No config file found, using defaults"),
+        }
+
+        self.settings = Config::load(path.as_deref())?;
+
+        let mut problems: Vec<String> = Vec::new();
+
+        let hid_info = self.hid_info();
+        match &self.settings.software {
+            Some(software) => match self.software_keyboard(software, None) {
+                Ok(_) => println!("Keymap: {}", software.keymap.display()),
+                Err(e) => problems.push(format!("software keymap: {e}")),
+            },
+            None => match Keyboard::new(&hid_info) {
+                Ok(_) => println!("Controller: found {:04x?}", hid_info),
+                Err(e) => problems.push(format!("controller: {e}")),
+            },
+        }
+
+        if self.settings.devices.is_empty() {
+            println!("Devices: none configured, one must be given on the command line");
+        }
+        for device in &self.settings.devices {
+            match std::fs::File::open(device) {
+                Ok(_) => println!("Device: {device}"),
+                Err(e) => problems.push(format!("device {device}: {e}")),
+            }
+        }
+        for mouse in &self.settings.mice {
+            let device = &mouse.device;
+            if mouse.buttons.is_empty() && mouse.axes.is_empty() {
+                problems.push(format!("mouse {device}: no buttons or axes are forwarded"));
+            }
+
+            match EventDevice::from_path_shared(device).and_then(|d| Ok(d.supported_keys()?)) {
+                Ok(keys) => {
+                    println!("Mouse: {device}");
+                    for button in mouse.buttons.iter().filter(|b| !keys.contains(b)) {
+                        problems.push(format!("mouse {device}: cannot send {}", button.as_ref()));
+                    }
+                }
+                Err(e) => problems.push(format!("mouse {device}: {e}")),
+            }
+        }
+
+        if self.settings.layouts.is_empty() {
+            println!(
+                "Layout: none configured, the built-in {} layout is used",
+                builtin_layouts::DEFAULT
+            );
+        }
+        for file in &self.settings.layouts {
+            match file.load() {
+                Ok(layout) => println!("Layout: {} ({} keys)", file, layout.layout.len()),
+                Err(e) => problems.push(format!("layout {}: {e}", file)),
+            }
+        }
+
+        if !self.settings.layouts.is_empty() && problems.is_empty() {
+            let layout = self.settings.load_layouts()?;
+            let options = ValidationOptions {
+                matrix_size: self.settings.matrix_size(),
+                device_keys: self.device_keys(&self.settings.devices)?,
+            };
+
+            for diagnostic in layout_validation::validate(&layout, &options) {
+                match diagnostic.severity {
+                    Severity::Error => problems.push(format!("layout: {}", diagnostic.message)),
+                    Severity::Warning => println!("Warning: layout: {}", diagnostic.message),
+                }
+            }
+        }
+
+        match self.settings.stages() {
+            Ok(stages) if stages.is_empty() => println!("Pipeline: empty"),
+            Ok(stages) => {
+                println!(
+                    "Pipeline: {}",
+                    stages
+                        .iter()
+                        .map(StageConfig::name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+
+                // Only the layout tells which keys a default passthrough takes
+                let chord_stage = stages.iter().position(|s| *s == StageConfig::PanicChord);
+                let passthrough = StageConfig::Passthrough { keys: vec![] };
+                let layout = match stages[..chord_stage.unwrap_or(0)].contains(&passthrough) {
+                    true => self.settings.load_layouts().ok(),
+                    false => None,
+                };
+                if let Some(layout) = layout {
+                    let mapper = MatrixMapper::new(&layout);
+                    for key in &self.settings.panic_chord {
+                        if mapper.positions(*key).is_empty() {
+                            problems.push(format!(
+                                "pipeline: {} is missing from the layout, so the passthrough stage takes it before the panic_chord stage",
+                                key.as_ref()
+                            ));
+                        }
+                    }
+                }
+            }
+            Err(e) => problems.push(format!("pipeline: {e}")),
+        }
+
+        if self.settings.panic_chord.contains(&KeyCode::NONE) {
+            problems.push("panic_chord: NONE can never be pressed".to_string());
+        }
+
+        if let Some(i3_config) = &self.settings.integrations.i3 {
+            for rule in &i3_config.rules {
+                if rule.class.is_none() && rule.title.is_none() {
+                    problems.push(format!(
+                        "integrations.i3: rule for layer {} matches nothing, set a class or title",
+                        rule.layer
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            println!("Configuration is valid");
+            Ok(())
+        } else {
+            for problem in &problems {
+                println!("Problem: {problem}");
+            }
+            Err(anyhow::anyhow!("{} problem(s) found", problems.len()))
+        }
+    }
 }
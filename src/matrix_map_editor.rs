@@ -0,0 +1,346 @@
+use std::io::Write;
+
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::matix_mapper::{EncoderMapping, Layout, LayoutItem, MatrixPosition};
+
+const CELL_WIDTH: usize = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Keys move the cursor and run commands
+    Navigate,
+    /// The next key pressed is assigned to the cell under the cursor
+    Assign,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EditorAction {
+    Continue,
+    Save,
+    Quit,
+}
+
+/// Interactive editor of the matrix position of each key. It only sees keys
+/// from the grabbed input device, so the commands are keys on it too.
+pub struct MatrixMapEditor {
+    /// Up to 256 each, so every row and column index fits in a u8
+    rows: u16,
+    cols: u16,
+    cells: Vec<Option<LayoutItem>>,
+    /// Kept as loaded, the editor only changes keys
+    encoders: Vec<EncoderMapping>,
+    cursor: (u8, u8),
+    mode: Mode,
+    /// Each step holds the previous content of every cell it changed
+    undo: Vec<Vec<(usize, Option<LayoutItem>)>>,
+    dirty: bool,
+    message: String,
+}
+
+impl MatrixMapEditor {
+    /// Starts from `layout`, growing the grid to fit every key already in it
+    pub fn new(rows: u8, cols: u8, layout: Option<Layout>) -> Self {
+        let Layout {
+            layout: items,
+            encoders,
+        } = layout.unwrap_or_default();
+        let rows = items
+            .iter()
+            .map(|i| u16::from(i.matrix.row) + 1)
+            .fold(rows.into(), u16::max);
+        let cols = items
+            .iter()
+            .map(|i| u16::from(i.matrix.col) + 1)
+            .fold(cols.into(), u16::max);
+
+        let mut cells = vec![None; rows as usize * cols as usize];
+        for item in items {
+            let index = item.matrix.row as usize * cols as usize + item.matrix.col as usize;
+            cells[index] = Some(item);
+        }
+
+        Self {
+            rows,
+            cols,
+            cells,
+            encoders,
+            cursor: (0, 0),
+            mode: Mode::Navigate,
+            undo: Vec::new(),
+            dirty: false,
+            message: String::new(),
+        }
+    }
+
+    pub fn label_at(&self, row: u8, col: u8) -> Option<&str> {
+        self.cells[self.index(row, col)]
+            .as_ref()
+            .map(|item| item.label.as_str())
+    }
+
+    fn index(&self, row: u8, col: u8) -> usize {
+        row as usize * self.cols as usize + col as usize
+    }
+
+    /// Handles a key press from the input device
+    pub fn handle(&mut self, code: KeyCode) -> EditorAction {
+        self.message.clear();
+
+        match self.mode {
+            Mode::Assign => {
+                self.assign(code);
+                self.mode = Mode::Navigate;
+            }
+            Mode::Navigate => match code {
+                KeyCode::UP => self.cursor.0 = self.cursor.0.saturating_sub(1),
+                KeyCode::DOWN if u16::from(self.cursor.0) + 1 < self.rows => self.cursor.0 += 1,
+                KeyCode::LEFT => self.cursor.1 = self.cursor.1.saturating_sub(1),
+                KeyCode::RIGHT if u16::from(self.cursor.1) + 1 < self.cols => self.cursor.1 += 1,
+                KeyCode::ENTER | KeyCode::SPACE => self.mode = Mode::Assign,
+                KeyCode::BACKSPACE | KeyCode::DELETE => self.clear(),
+                KeyCode::U => self.undo(),
+                KeyCode::S => return EditorAction::Save,
+                KeyCode::Q | KeyCode::ESC if self.dirty => {
+                    self.dirty = false;
+                    self.message = "Unsaved changes, press Q again to quit".to_string();
+                }
+                KeyCode::Q | KeyCode::ESC => return EditorAction::Quit,
+                _ => {}
+            },
+        }
+
+        EditorAction::Continue
+    }
+
+    /// Puts `code` under the cursor, taking it away from any other cell, then
+    /// moves on to the next cell
+    fn assign(&mut self, code: KeyCode) {
+        let (row, col) = self.cursor;
+        let index = self.index(row, col);
+        let label = format!("{:?}", code);
+        let mut changes = Vec::new();
+
+        let has_code = |item: &Option<LayoutItem>| {
+            item.as_ref()
+                .is_some_and(|item| item.label.parse::<KeyCode>().ok() == Some(code))
+        };
+
+        if has_code(&self.cells[index]) {
+            self.message = format!("{label} is already there");
+            return;
+        }
+
+        if let Some(previous) = self.cells.iter().position(has_code) {
+            changes.push((previous, self.cells[previous].take()));
+            self.message = format!("{label} moved here");
+        }
+
+        let item = match self.cells[index].clone() {
+            Some(item) => LayoutItem { label, ..item },
+            None => LayoutItem {
+                matrix: MatrixPosition { row, col },
+                x: col as f32,
+                y: row as f32,
+                label,
+                ..Default::default()
+            },
+        };
+        changes.push((index, self.cells[index].replace(item)));
+        self.push_undo(changes);
+
+        self.cursor = if u16::from(col) + 1 < self.cols {
+            (row, col + 1)
+        } else if u16::from(row) + 1 < self.rows {
+            (row + 1, 0)
+        } else {
+            (row, col)
+        };
+    }
+
+    fn clear(&mut self) {
+        let index = self.index(self.cursor.0, self.cursor.1);
+        if let Some(item) = self.cells[index].take() {
+            self.push_undo(vec![(index, Some(item))]);
+        }
+    }
+
+    fn undo(&mut self) {
+        match self.undo.pop() {
+            Some(changes) => {
+                for (index, item) in changes.into_iter().rev() {
+                    self.cells[index] = item;
+                }
+                self.dirty = true;
+            }
+            None => self.message = "Nothing to undo".to_string(),
+        }
+    }
+
+    fn push_undo(&mut self, changes: Vec<(usize, Option<LayoutItem>)>) {
+        self.undo.push(changes);
+        self.dirty = true;
+    }
+
+    pub fn mark_saved(&mut self, message: String) {
+        self.dirty = false;
+        self.message = message;
+    }
+
+    pub fn to_layout(&self) -> Layout {
+        Layout {
+            layout: self.cells.iter().flatten().cloned().collect(),
+            encoders: self.encoders.clone(),
+        }
+    }
+
+    /// Draws the grid and a status line, the cell under the cursor in reverse
+    /// video
+    pub fn render<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        write!(out, "\x1b[H\x1b[2J")?;
+
+        write!(out, "    ")?;
+        for col in 0..self.cols {
+            write!(out, "{:^CELL_WIDTH$}", col)?;
+        }
+        write!(out, "\r\n")?;
+
+        for row in (0..self.rows).map(|row| row as u8) {
+            write!(out, "{:>3} ", row)?;
+            for col in (0..self.cols).map(|col| col as u8) {
+                let label = self.label_at(row, col).unwrap_or("·");
+                let label: String = label.chars().take(CELL_WIDTH - 1).collect();
+
+                if (row, col) == self.cursor {
+                    write!(out, "\x1b[7m{:^CELL_WIDTH$}\x1b[0m", label)?;
+                } else {
+                    write!(out, "{:^CELL_WIDTH$}", label)?;
+                }
+            }
+            write!(out, "\r\n")?;
+        }
+
+        write!(out, "\r\n")?;
+        match self.mode {
+            Mode::Navigate => write!(
+                out,
+                "[{},{}] Arrows: move  Enter/Space: assign  Backspace/Delete: clear  U: undo  S: save  Q/Esc: quit\r\n",
+                self.cursor.0, self.cursor.1
+            )?,
+            Mode::Assign => write!(
+                out,
+                "[{},{}] Press the key at this position\r\n",
+                self.cursor.0, self.cursor.1
+            )?,
+        }
+        write!(out, "{}\r\n", self.message)?;
+
+        out.flush()
+    }
+}
+
+/// Switches the terminal to its alternate screen until dropped
+pub struct AlternateScreen;
+
+impl AlternateScreen {
+    pub fn enter() -> std::io::Result<Self> {
+        let mut stderr = std::io::stderr();
+        write!(stderr, "\x1b[?1049h\x1b[?25l")?;
+        stderr.flush()?;
+
+        Ok(Self)
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\x1b[?25h\x1b[?1049l");
+        let _ = stderr.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_event::key_code::KeyCode;
+    use crate::matrix_map_editor::{EditorAction, MatrixMapEditor, Mode};
+
+    #[test]
+    fn test_assign_and_undo() {
+        let mut editor = MatrixMapEditor::new(2, 2, None);
+
+        editor.handle(KeyCode::ENTER);
+        assert_eq!(editor.mode, Mode::Assign);
+        editor.handle(KeyCode::A);
+        assert_eq!(editor.label_at(0, 0), Some("A"));
+        assert_eq!(editor.cursor, (0, 1));
+
+        editor.handle(KeyCode::SPACE);
+        editor.handle(KeyCode::UP);
+        assert_eq!(editor.cursor, (1, 0));
+
+        // Assigning a key again moves it
+        editor.handle(KeyCode::ENTER);
+        editor.handle(KeyCode::A);
+        assert_eq!(editor.label_at(0, 0), None);
+        assert_eq!(editor.label_at(1, 0), Some("A"));
+
+        editor.handle(KeyCode::U);
+        assert_eq!(editor.label_at(0, 0), Some("A"));
+        assert_eq!(editor.label_at(1, 0), None);
+
+        editor.handle(KeyCode::LEFT);
+        editor.handle(KeyCode::UP);
+        editor.handle(KeyCode::BACKSPACE);
+        assert_eq!(editor.label_at(0, 0), None);
+        editor.handle(KeyCode::U);
+        assert_eq!(editor.to_layout().layout.len(), 2);
+
+        assert_eq!(editor.handle(KeyCode::Q), EditorAction::Continue);
+        assert_eq!(editor.handle(KeyCode::Q), EditorAction::Quit);
+    }
+
+    #[test]
+    fn test_last_row_and_column() {
+        let layout = serde_json::from_str(
+            r#"{ "layout": [{ "matrix": [255, 255], "x": 0, "y": 0, "label": "ESC" }] }"#,
+        )
+        .unwrap();
+        let mut editor = MatrixMapEditor::new(1, 1, Some(layout));
+        assert_eq!(editor.label_at(255, 255), Some("ESC"));
+
+        editor.cursor = (255, 254);
+        editor.handle(KeyCode::ENTER);
+        editor.handle(KeyCode::A);
+        assert_eq!(editor.cursor, (255, 255));
+        editor.handle(KeyCode::DOWN);
+        editor.handle(KeyCode::RIGHT);
+        editor.handle(KeyCode::ENTER);
+        editor.handle(KeyCode::B);
+        assert_eq!(editor.cursor, (255, 255));
+        assert_eq!(editor.label_at(255, 255), Some("B"));
+    }
+
+    #[test]
+    fn test_load_layout() {
+        let layout = serde_json::from_str(
+            r#"{ "layout": [
+                { "matrix": [3, 1], "x": 5, "y": 4, "label": "ESC", "aliases": ["GRAVE"] }
+            ] }"#,
+        )
+        .unwrap();
+        let mut editor = MatrixMapEditor::new(1, 1, Some(layout));
+        assert_eq!(editor.label_at(3, 1), Some("ESC"));
+
+        editor.handle(KeyCode::DOWN);
+        editor.handle(KeyCode::DOWN);
+        editor.handle(KeyCode::DOWN);
+        editor.handle(KeyCode::RIGHT);
+        editor.handle(KeyCode::ENTER);
+        editor.handle(KeyCode::CAPSLOCK);
+
+        let layout = editor.to_layout();
+        assert_eq!(layout.layout[0].label, "CAPSLOCK");
+        assert_eq!((layout.layout[0].x, layout.layout[0].y), (5.0, 4.0));
+        assert_eq!(layout.layout[0].aliases, vec!["GRAVE"]);
+    }
+}
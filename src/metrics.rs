@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::error;
+
+const PREFIX: &str = "qmk_virtual_keyboard";
+
+/// How often the textfile collector output is rewritten
+const TEXTFILE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bounds of the response latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations at or below each of `LATENCY_BUCKETS`, not cumulative
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counters and histograms of what the daemon does, rendered in the
+/// Prometheus text exposition format
+#[derive(Default)]
+pub struct Metrics {
+    input_events: Mutex<BTreeMap<String, u64>>,
+    matrix_presses: AtomicU64,
+    matrix_releases: AtomicU64,
+    encoder_turns: AtomicU64,
+    layer_switches: AtomicU64,
+    hid_errors: AtomicU64,
+    reconnects: AtomicU64,
+    reloads: AtomicU64,
+    response_latency: Histogram,
+}
+
+impl Metrics {
+    /// Counts an event read from the device at `path`
+    pub fn input_event(&self, path: &str) {
+        *self
+            .input_events
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry(path.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn matrix_update(&self, pressed: bool) {
+        match pressed {
+            true => self.matrix_presses.fetch_add(1, Ordering::Relaxed),
+            false => self.matrix_releases.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn encoder_turn(&self) {
+        self.encoder_turns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn layer_switch(&self) {
+        self.layer_switches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reload(&self) {
+        self.reloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the time an operation took to be answered, or that it failed
+    pub fn response(&self, latency: Duration, ok: bool) {
+        match ok {
+            true => self.response_latency.observe(latency),
+            false => {
+                self.hid_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        header(
+            &mut out,
+            "input_events_total",
+            "Input events read, by device path",
+            "counter",
+        );
+        for (device, count) in self
+            .input_events
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "{PREFIX}_input_events_total{{device=\"{}\"}} {count}",
+                escape(device)
+            );
+        }
+
+        header(
+            &mut out,
+            "matrix_updates_total",
+            "Matrix positions pressed and released on the controller",
+            "counter",
+        );
+        for (state, counter) in [
+            ("pressed", &self.matrix_presses),
+            ("released", &self.matrix_releases),
+        ] {
+            let _ = writeln!(
+                out,
+                "{PREFIX}_matrix_updates_total{{state=\"{state}\"}} {}",
+                load(counter)
+            );
+        }
+
+        for (name, help, counter) in [
+            (
+                "encoder_turns_total",
+                "Encoder steps sent to the controller",
+                &self.encoder_turns,
+            ),
+            (
+                "layer_switches_total",
+                "Layer changes sent to the controller",
+                &self.layer_switches,
+            ),
+            (
+                "hid_errors_total",
+                "Operations the controller did not answer",
+                &self.hid_errors,
+            ),
+            (
+                "reconnects_total",
+                "Times the controller was connected to again after failing",
+                &self.reconnects,
+            ),
+            (
+                "reloads_total",
+                "Configuration and layout reloads",
+                &self.reloads,
+            ),
+        ] {
+            header(&mut out, name, help, "counter");
+            let _ = writeln!(out, "{PREFIX}_{name} {}", load(counter));
+        }
+
+        let latency = &self.response_latency;
+        header(
+            &mut out,
+            "response_seconds",
+            "Time the controller took to answer an operation",
+            "histogram",
+        );
+        let mut cumulative = 0;
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(&latency.buckets) {
+            cumulative += load(bucket);
+            let _ = writeln!(
+                out,
+                "{PREFIX}_response_seconds_bucket{{le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let count = load(&latency.count);
+        let _ = writeln!(
+            out,
+            "{PREFIX}_response_seconds_bucket{{le=\"+Inf\"}} {count}"
+        );
+        let _ = writeln!(
+            out,
+            "{PREFIX}_response_seconds_sum {}",
+            load(&latency.sum_micros) as f64 / 1e6
+        );
+        let _ = writeln!(out, "{PREFIX}_response_seconds_count {count}");
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {PREFIX}_{name} {kind}");
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Answers `GET /metrics` on `address` in a background thread, each client on
+/// its own thread so a slow one doesn't hold up the others
+pub fn serve(address: &str, metrics: Arc<Metrics>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let metrics = metrics.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = respond(stream, &metrics) {
+                            error!("Metrics client error: {:?}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Metrics accept error: {:?}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(stream: TcpStream, metrics: &Metrics) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut request_line = String::new();
+    BufReader::new(stream).read_line(&mut request_line)?;
+
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "Not found, try /metrics\n".to_string()),
+    };
+
+    write!(
+        writer,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    Ok(())
+}
+
+/// Keeps `path` up to date for node_exporter's textfile collector, replacing
+/// it at once so it is never read half written
+pub fn write_textfile(path: &Path, metrics: Arc<Metrics>) {
+    let path = path.to_path_buf();
+    let mut temporary = PathBuf::from(&path).into_os_string();
+    temporary.push(".tmp");
+
+    std::thread::spawn(move || loop {
+        let result = std::fs::write(&temporary, metrics.render())
+            .and_then(|_| std::fs::rename(&temporary, &path));
+        if let Err(e) = result {
+            error!("Could not write metrics to {}: {:?}", path.display(), e);
+        }
+
+        std::thread::sleep(TEXTFILE_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.input_event("/dev/input/\"event3\"");
+        metrics.input_event("/dev/input/\"event3\"");
+        metrics.input_event("/dev/input/event4");
+        metrics.reconnect();
+        metrics.matrix_update(true);
+        metrics.response(Duration::from_micros(800), true);
+        metrics.response(Duration::from_millis(30), true);
+        metrics.response(Duration::from_secs(10), false);
+
+        let rendered = metrics.render();
+        for line in [
+            "# TYPE qmk_virtual_keyboard_input_events_total counter",
+            r#"qmk_virtual_keyboard_input_events_total{device="/dev/input/\"event3\""} 2"#,
+            r#"qmk_virtual_keyboard_input_events_total{device="/dev/input/event4"} 1"#,
+            "qmk_virtual_keyboard_reconnects_total 1",
+            r#"qmk_virtual_keyboard_matrix_updates_total{state="pressed"} 1"#,
+            r#"qmk_virtual_keyboard_matrix_updates_total{state="released"} 0"#,
+            "qmk_virtual_keyboard_hid_errors_total 1",
+            r#"qmk_virtual_keyboard_response_seconds_bucket{le="0.0005"} 0"#,
+            r#"qmk_virtual_keyboard_response_seconds_bucket{le="0.001"} 1"#,
+            r#"qmk_virtual_keyboard_response_seconds_bucket{le="0.05"} 2"#,
+            r#"qmk_virtual_keyboard_response_seconds_bucket{le="+Inf"} 2"#,
+            "qmk_virtual_keyboard_response_seconds_sum 0.0308",
+            "qmk_virtual_keyboard_response_seconds_count 2",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "{line} missing in\n{rendered}"
+            );
+        }
+    }
+}
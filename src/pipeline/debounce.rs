@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use log::info;
+
+use crate::config::ChatterConfig;
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::{KeyEvent, MyTime};
+use crate::pipeline::{Context, Middleware};
+
+/// Drops the bounces of worn switches. Each press and release is held back
+/// for the threshold of its key: a press released within it is dropped along
+/// with its release, as is a release pressed again within it, so a key held
+/// through chatter is seen held once. Judged on the kernel's event timestamps.
+pub struct ChatterFilter {
+    threshold: Duration,
+    overrides: HashMap<KeyCode, Duration>,
+    /// Presses and releases held back, by key
+    pending: HashMap<KeyCode, KeyEvent>,
+    /// Keys whose press was let through, and not their release yet
+    pressed: HashSet<KeyCode>,
+}
+
+impl ChatterFilter {
+    pub fn new(config: &ChatterConfig) -> Self {
+        Self {
+            threshold: Duration::from_millis(config.threshold),
+            overrides: config
+                .overrides
+                .iter()
+                .map(|(code, ms)| (*code, Duration::from_millis(*ms)))
+                .collect(),
+            pending: HashMap::new(),
+            pressed: HashSet::new(),
+        }
+    }
+
+    fn threshold(&self, code: KeyCode) -> Duration {
+        self.overrides.get(&code).copied().unwrap_or(self.threshold)
+    }
+
+    /// Pushes `event` to `output` if it goes through right away, after the
+    /// held back events that are due by its time
+    pub fn filter(&mut self, event: KeyEvent, output: &mut Vec<KeyEvent>) {
+        let (code, time) = match event {
+            KeyEvent::Press(code, _, time) | KeyEvent::Release(code, time) => (code, time),
+            event => return output.push(event),
+        };
+        self.flush(time, output);
+
+        let threshold = self.threshold(code);
+        match (event, self.pending.get(&code)) {
+            // A repeat only goes with a press let through
+            (KeyEvent::Press(_, true, _), _) => {
+                if self.pressed.contains(&code) && !self.pending.contains_key(&code) {
+                    output.push(event);
+                }
+            }
+            (KeyEvent::Release(..), Some(KeyEvent::Press(_, _, pressed))) => {
+                info!(
+                    "Dropped chatter on {:?}, pressed for {}ms",
+                    code,
+                    time.since(*pressed).as_millis()
+                );
+                self.pending.remove(&code);
+            }
+            (KeyEvent::Press(..), Some(KeyEvent::Release(_, released))) => {
+                info!(
+                    "Dropped chatter on {:?}, released for {}ms",
+                    code,
+                    time.since(*released).as_millis()
+                );
+                self.pending.remove(&code);
+            }
+            // Another device repeating what is already held back
+            (_, Some(_)) => {}
+            // Nothing is held back without a threshold, nor the release of a
+            // key pressed before the filter started
+            (KeyEvent::Press(..), _) if threshold.is_zero() => self.let_through(event, output),
+            (KeyEvent::Release(..), _) if threshold.is_zero() || !self.pressed.contains(&code) => {
+                self.let_through(event, output)
+            }
+            _ => {
+                self.pending.insert(code, event);
+            }
+        }
+    }
+
+    /// Pushes the held back events due by `now` to `output`, in time order
+    pub fn flush(&mut self, now: MyTime, output: &mut Vec<KeyEvent>) {
+        let mut due: Vec<(MyTime, KeyEvent)> = self
+            .pending
+            .iter()
+            .filter_map(|(code, event)| Some((self.due(*code, event)?, *event)))
+            .filter(|(due, _)| *due <= now)
+            .collect();
+        due.sort_by_key(|(due, _)| *due);
+
+        for (_, event) in due {
+            if let KeyEvent::Press(code, ..) | KeyEvent::Release(code, _) = event {
+                self.pending.remove(&code);
+            }
+            self.let_through(event, output);
+        }
+    }
+
+    /// When a held back press or release goes through
+    fn due(&self, code: KeyCode, event: &KeyEvent) -> Option<MyTime> {
+        match event {
+            KeyEvent::Press(_, _, time) | KeyEvent::Release(_, time) => {
+                Some(time.after(self.threshold(code)))
+            }
+            _ => None,
+        }
+    }
+
+    fn let_through(&mut self, event: KeyEvent, output: &mut Vec<KeyEvent>) {
+        match event {
+            KeyEvent::Press(code, ..) => self.pressed.insert(code),
+            KeyEvent::Release(code, _) => self.pressed.remove(&code),
+            _ => false,
+        };
+        output.push(event);
+    }
+}
+
+impl Middleware for ChatterFilter {
+    fn process(
+        &mut self,
+        event: KeyEvent,
+        _context: &mut Context,
+        output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()> {
+        self.filter(event, output);
+
+        Ok(())
+    }
+
+    fn deadline(&self) -> Option<MyTime> {
+        self.pending
+            .iter()
+            .filter_map(|(code, event)| self.due(*code, event))
+            .min()
+    }
+
+    fn tick(
+        &mut self,
+        now: MyTime,
+        _context: &mut Context,
+        output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()> {
+        self.flush(now, output);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::config::ChatterConfig;
+    use crate::key_event::key_code::KeyCode;
+    use crate::key_event::KeyEvent;
+    use crate::pipeline::debounce::ChatterFilter;
+    use crate::test_fixtures::at;
+
+    fn filter(chatter: &mut ChatterFilter, event: KeyEvent) -> Vec<KeyEvent> {
+        let mut output = Vec::new();
+        chatter.filter(event, &mut output);
+        output
+    }
+
+    fn flush(chatter: &mut ChatterFilter, now: i64) -> Vec<KeyEvent> {
+        let mut output = Vec::new();
+        chatter.flush(at(now), &mut output);
+        output
+    }
+
+    #[test]
+    fn test_chatter() {
+        let mut chatter = ChatterFilter::new(&ChatterConfig {
+            threshold: 30,
+            overrides: HashMap::from([(KeyCode::SPACE, 0)]),
+        });
+        let c = &mut chatter;
+
+        // A press goes through once it lasted the threshold
+        assert_eq!(
+            filter(c, KeyEvent::Press(KeyCode::A, false, at(1000))),
+            vec![]
+        );
+        assert_eq!(
+            flush(c, 1030),
+            vec![KeyEvent::Press(KeyCode::A, false, at(1000))]
+        );
+        assert_eq!(
+            filter(c, KeyEvent::Press(KeyCode::A, true, at(1050))),
+            vec![KeyEvent::Press(KeyCode::A, true, at(1050))]
+        );
+
+        // Bounces up and down while held, then is released for good
+        for event in [
+            KeyEvent::Release(KeyCode::A, at(1080)),
+            KeyEvent::Press(KeyCode::A, false, at(1083)),
+            KeyEvent::Release(KeyCode::A, at(1090)),
+            KeyEvent::Press(KeyCode::A, false, at(1092)),
+            KeyEvent::Release(KeyCode::A, at(1110)),
+        ] {
+            assert_eq!(filter(c, event), vec![], "{:?}", event);
+        }
+        assert_eq!(flush(c, 1139), vec![]);
+        assert_eq!(
+            flush(c, 1140),
+            vec![KeyEvent::Release(KeyCode::A, at(1110))]
+        );
+
+        // A first press bouncing down and up goes nowhere
+        assert_eq!(
+            filter(c, KeyEvent::Press(KeyCode::B, false, at(1200))),
+            vec![]
+        );
+        assert_eq!(filter(c, KeyEvent::Release(KeyCode::B, at(1205))), vec![]);
+        assert_eq!(flush(c, 1300), vec![]);
+
+        // Later events let the earlier ones through first, keys without a
+        // threshold go through right away
+        assert_eq!(
+            filter(c, KeyEvent::Press(KeyCode::C, false, at(1400))),
+            vec![]
+        );
+        assert_eq!(
+            filter(c, KeyEvent::Press(KeyCode::SPACE, false, at(1440))),
+            vec![
+                KeyEvent::Press(KeyCode::C, false, at(1400)),
+                KeyEvent::Press(KeyCode::SPACE, false, at(1440))
+            ]
+        );
+        assert_eq!(
+            filter(c, KeyEvent::Release(KeyCode::SPACE, at(1445))),
+            vec![KeyEvent::Release(KeyCode::SPACE, at(1445))]
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use std::collections::HashSet;
+
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::KeyEvent;
+use crate::pipeline::{Context, Middleware};
+
+/// Drops every event of some keys
+pub struct Filter(HashSet<KeyCode>);
+
+impl Filter {
+    pub fn new(keys: &[KeyCode]) -> Self {
+        Self(keys.iter().copied().collect())
+    }
+}
+
+impl Middleware for Filter {
+    fn process(
+        &mut self,
+        event: KeyEvent,
+        _context: &mut Context,
+        output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()> {
+        match event {
+            KeyEvent::Press(code, ..) | KeyEvent::Release(code, _) if self.0.contains(&code) => {}
+            event => output.push(event),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use log::{debug, warn};
+
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::matix_mapper::{MatrixMapper, MatrixPosition};
+use crate::key_event::{KeyEvent, SyncKind};
+use crate::keyboard::{EncoderDirection, Operation};
+use crate::pipeline::Context;
+
+/// The last stage of every pipeline, turning what the other stages let through
+/// into operations on the controller's matrix and encoders
+#[derive(Default)]
+pub struct Mapping {
+    /// Keys currently held down, with the positions each press of them
+    /// pressed. Several devices can hold the same key, it is only let go of
+    /// once all of them released it.
+    pressed: HashMap<KeyCode, Vec<Vec<MatrixPosition>>>,
+}
+
+impl Mapping {
+    pub fn process(&mut self, event: KeyEvent, context: &Context, output: &mut Vec<Operation>) {
+        match event {
+            KeyEvent::Press(code, false, _) if code != KeyCode::NONE => {
+                let positions = context.mapper.positions(code).to_vec();
+                if positions.is_empty() {
+                    debug!("{:?} is not in the layout", code);
+                }
+
+                // The keyboard counts references, so positions shared with
+                // other held keys are only pressed once
+                output.extend(
+                    positions
+                        .iter()
+                        .map(|pos| Operation::UpdateMatrix(true, pos.row, pos.col)),
+                );
+                self.pressed.entry(code).or_default().push(positions);
+            }
+            KeyEvent::Release(code, _) => {
+                let Some(presses) = self.pressed.get_mut(&code) else {
+                    return;
+                };
+                let positions = presses.pop().unwrap_or_default();
+                if presses.is_empty() {
+                    self.pressed.remove(&code);
+                }
+
+                output.extend(
+                    positions
+                        .into_iter()
+                        .map(|pos| Operation::UpdateMatrix(false, pos.row, pos.col)),
+                );
+            }
+            KeyEvent::Relative(axis, steps, _) => {
+                if let Some(mapping) = context.mapper.encoder(axis) {
+                    let direction = match (steps > 0) != mapping.reverse {
+                        true => EncoderDirection::Clockwise,
+                        false => EncoderDirection::CounterClockwise,
+                    };
+
+                    output.extend(
+                        (0..steps.unsigned_abs())
+                            .map(|_| Operation::EncoderTurn(mapping.encoder, direction)),
+                    );
+                }
+            }
+            KeyEvent::Sync(SyncKind::Dropped, ..) => {
+                warn!("Input events were dropped, held keys may be out of sync");
+            }
+            _ => {}
+        }
+    }
+
+    /// Releases every held key, forgetting them
+    pub fn release_all(&mut self) -> Vec<Operation> {
+        self.pressed
+            .drain()
+            .flat_map(|(code, presses)| {
+                presses.into_iter().flatten().map(move |pos| {
+                    debug!("Releasing {:?} from {:?}", code, pos);
+                    Operation::UpdateMatrix(false, pos.row, pos.col)
+                })
+            })
+            .collect()
+    }
+
+    /// Releases the positions held keys no longer map to in `mapper`, keeping
+    /// the others held
+    pub fn remap(&mut self, mapper: &MatrixMapper) -> Vec<Operation> {
+        let mut released = Vec::new();
+
+        for (code, positions) in self
+            .pressed
+            .iter_mut()
+            .flat_map(|(code, presses)| presses.iter_mut().map(move |p| (code, p)))
+        {
+            let new_positions = mapper.positions(*code);
+            let (kept, gone): (Vec<MatrixPosition>, Vec<MatrixPosition>) =
+                positions.iter().partition(|p| new_positions.contains(p));
+
+            for pos in gone {
+                debug!("Releasing {:?} from {:?} as its mapping changed", code, pos);
+                released.push(Operation::UpdateMatrix(false, pos.row, pos.col));
+            }
+            *positions = kept;
+        }
+
+        released
+    }
+}
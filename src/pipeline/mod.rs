@@ -0,0 +1,245 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::config::{Config, StageConfig};
+use crate::key_event::matix_mapper::MatrixMapper;
+use crate::key_event::{KeyEvent, MyTime};
+use crate::keyboard::Operation;
+use crate::uinput::Identity;
+
+use self::mapping::Mapping;
+
+pub mod debounce;
+pub mod filter;
+pub mod mapping;
+pub mod panic_chord;
+pub mod passthrough;
+pub mod remap;
+pub mod stats;
+
+/// What stages know of the daemon while handling an event
+pub struct Context<'a> {
+    pub mapper: &'a MatrixMapper,
+    /// Layer last reported by the controller
+    pub layer: Option<u8>,
+    /// Set by a stage to stop the daemon, releasing everything it holds
+    pub stop: bool,
+}
+
+/// A step input events go through on their way to the controller
+pub trait Middleware {
+    /// Handles `event`, pushing what goes on to the next stage to `output`
+    fn process(
+        &mut self,
+        event: KeyEvent,
+        context: &mut Context,
+        output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()>;
+
+    /// When the stage next has events it held back to let through, if any
+    fn deadline(&self) -> Option<MyTime> {
+        None
+    }
+
+    /// Called once `deadline` passed, pushing the events that are due at `now`
+    /// to `output`
+    fn tick(
+        &mut self,
+        _now: MyTime,
+        _context: &mut Context,
+        _output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called when the stage is no longer used
+    fn finish(&mut self) {}
+}
+
+/// Stages run in order, the events out of the last one are mapped to matrix
+/// operations by the built-in mapping stage
+pub struct Pipeline {
+    stages: Vec<Box<dyn Middleware>>,
+    mapping: Mapping,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<Box<dyn Middleware>>) -> Self {
+        Self {
+            stages,
+            mapping: Mapping::default(),
+        }
+    }
+
+    /// Builds the stages of `settings`. Statistics are not recorded while
+    /// `stats_paused` is set, and the passthrough keyboard takes `identity`.
+    pub fn from_config(
+        settings: &Config,
+        stats_paused: Arc<AtomicBool>,
+        identity: &Identity,
+    ) -> anyhow::Result<Self> {
+        let stages = settings
+            .stages()?
+            .iter()
+            .map(|stage| -> anyhow::Result<Box<dyn Middleware>> {
+                Ok(match stage {
+                    StageConfig::Remap { keys } => Box::new(remap::Remap::new(keys.clone())),
+                    StageConfig::Filter { keys } => Box::new(filter::Filter::new(keys)),
+                    StageConfig::Debounce => Box::new(debounce::ChatterFilter::new(
+                        settings.chatter.as_ref().expect("checked by stages"),
+                    )),
+                    StageConfig::Stats => Box::new(stats::StatsStage::new(
+                        settings.stats.as_ref().expect("checked by stages"),
+                        stats_paused.clone(),
+                    )?),
+                    StageConfig::Passthrough { keys } => {
+                        Box::new(passthrough::Passthrough::new(keys, identity)?)
+                    }
+                    StageConfig::PanicChord => {
+                        Box::new(panic_chord::PanicChord::new(&settings.panic_chord))
+                    }
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self::new(stages))
+    }
+
+    /// Runs `event` through every stage, returning the operations the events
+    /// out of the last one map to
+    pub fn process(
+        &mut self,
+        event: KeyEvent,
+        context: &mut Context,
+    ) -> anyhow::Result<Vec<Operation>> {
+        self.run_from(0, vec![event], context)
+    }
+
+    /// When a stage next has events it held back to let through
+    pub fn deadline(&self) -> Option<MyTime> {
+        self.stages
+            .iter()
+            .filter_map(|stage| stage.deadline())
+            .min()
+    }
+
+    /// Runs the events stages let through at `now` through the stages after
+    /// them, returning the operations they map to
+    pub fn tick(&mut self, now: MyTime, context: &mut Context) -> anyhow::Result<Vec<Operation>> {
+        let mut operations = Vec::new();
+
+        for i in 0..self.stages.len() {
+            if self.stages[i]
+                .deadline()
+                .is_none_or(|deadline| deadline > now)
+            {
+                continue;
+            }
+
+            let mut events = Vec::new();
+            self.stages[i].tick(now, context, &mut events)?;
+            operations.extend(self.run_from(i + 1, events, context)?);
+        }
+
+        Ok(operations)
+    }
+
+    /// Runs `events` through the stages from the `first` one on, then maps
+    /// them
+    fn run_from(
+        &mut self,
+        first: usize,
+        mut events: Vec<KeyEvent>,
+        context: &mut Context,
+    ) -> anyhow::Result<Vec<Operation>> {
+        for stage in &mut self.stages[first..] {
+            let mut output = Vec::new();
+            for event in events {
+                stage.process(event, context, &mut output)?;
+            }
+            events = output;
+        }
+
+        let mut operations = Vec::new();
+        for event in events {
+            self.mapping.process(event, context, &mut operations);
+        }
+
+        Ok(operations)
+    }
+
+    /// Releases every key held through the pipeline
+    pub fn release_all(&mut self) -> Vec<Operation> {
+        self.mapping.release_all()
+    }
+
+    /// Releases the positions held keys no longer map to once `mapper` is used
+    pub fn remap(&mut self, mapper: &MatrixMapper) -> Vec<Operation> {
+        self.mapping.remap(mapper)
+    }
+
+    pub fn finish(&mut self) {
+        for stage in &mut self.stages {
+            stage.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::key_event::builtin_layouts;
+    use crate::key_event::key_code::KeyCode;
+    use crate::key_event::matix_mapper::MatrixMapper;
+    use crate::key_event::{KeyEvent, MyTime};
+    use crate::keyboard::Operation;
+    use crate::pipeline::filter::Filter;
+    use crate::pipeline::panic_chord::PanicChord;
+    use crate::pipeline::remap::Remap;
+    use crate::pipeline::{Context, Pipeline};
+
+    #[test]
+    fn test_pipeline() {
+        let mapper = MatrixMapper::new(&builtin_layouts::builtin("laptop").unwrap());
+        let mut pipeline = Pipeline::new(vec![
+            Box::new(Remap::new(HashMap::from([(
+                KeyCode::CAPSLOCK,
+                KeyCode::ESC,
+            )]))),
+            Box::new(Filter::new(&[KeyCode::INSERT])),
+            Box::new(PanicChord::new(&[KeyCode::LEFTCTRL, KeyCode::ESC])),
+        ]);
+        let mut context = Context {
+            mapper: &mapper,
+            layer: None,
+            stop: false,
+        };
+        let time = MyTime::default();
+        let esc = mapper.positions(KeyCode::ESC)[0];
+        let ctrl = mapper.positions(KeyCode::LEFTCTRL)[0];
+        let mut process = |event| pipeline.process(event, &mut context).unwrap();
+
+        assert_eq!(
+            process(KeyEvent::Press(KeyCode::CAPSLOCK, false, time)),
+            vec![Operation::UpdateMatrix(true, esc.row, esc.col)]
+        );
+        assert_eq!(
+            process(KeyEvent::Release(KeyCode::CAPSLOCK, time)),
+            vec![Operation::UpdateMatrix(false, esc.row, esc.col)]
+        );
+        assert_eq!(
+            process(KeyEvent::Press(KeyCode::INSERT, false, time)),
+            vec![]
+        );
+        assert_eq!(
+            process(KeyEvent::Press(KeyCode::LEFTCTRL, false, time)),
+            vec![Operation::UpdateMatrix(true, ctrl.row, ctrl.col)]
+        );
+        assert_eq!(
+            process(KeyEvent::Press(KeyCode::CAPSLOCK, false, time)),
+            vec![]
+        );
+        assert!(context.stop);
+    }
+}
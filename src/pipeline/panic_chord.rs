@@ -0,0 +1,51 @@
+use std::collections::HashSet;
+
+use log::warn;
+
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::KeyEvent;
+use crate::pipeline::{Context, Middleware};
+
+/// Stops the daemon once every key of the chord is held, releasing the grab.
+/// The press completing the chord goes no further.
+pub struct PanicChord {
+    chord: Vec<KeyCode>,
+    held: HashSet<KeyCode>,
+}
+
+impl PanicChord {
+    pub fn new(chord: &[KeyCode]) -> Self {
+        Self {
+            chord: chord.to_vec(),
+            held: HashSet::new(),
+        }
+    }
+}
+
+impl Middleware for PanicChord {
+    fn process(
+        &mut self,
+        event: KeyEvent,
+        context: &mut Context,
+        output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()> {
+        match event {
+            KeyEvent::Press(code, false, _) => {
+                self.held.insert(code);
+                if !self.chord.is_empty() && self.chord.iter().all(|c| self.held.contains(c)) {
+                    warn!("Panic chord pressed, exiting");
+                    context.stop = true;
+                    return Ok(());
+                }
+            }
+            KeyEvent::Release(code, _) => {
+                self.held.remove(&code);
+            }
+            _ => {}
+        }
+
+        output.push(event);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+
+use log::error;
+
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::KeyEvent;
+use crate::pipeline::{Context, Middleware};
+use crate::uinput::{Identity, UinputDevice};
+
+/// Types some keys on a virtual keyboard instead of sending them to the
+/// controller, by default the keys missing from the layout
+pub struct Passthrough {
+    keys: Vec<KeyCode>,
+    device: UinputDevice,
+    /// Keys pressed on the virtual keyboard, released there too whatever the
+    /// layout says by then
+    held: HashSet<KeyCode>,
+}
+
+impl Passthrough {
+    pub fn new(keys: &[KeyCode], identity: &Identity) -> anyhow::Result<Self> {
+        let device = identity
+            .keyboard("qmk-virtual-keyboard passthrough")
+            .keys(declared_keys(keys))
+            .retain_keys(|code| declares(keys, code))
+            .build()?;
+
+        Ok(Self {
+            keys: keys.to_vec(),
+            device,
+            held: HashSet::new(),
+        })
+    }
+
+    fn passes(&self, code: KeyCode, context: &Context) -> bool {
+        match self.keys.is_empty() {
+            true => context.mapper.positions(code).is_empty(),
+            false => self.keys.contains(&code),
+        }
+    }
+
+    fn send(&mut self, event: KeyEvent) {
+        if let Err(e) = self.device.send_event(event) {
+            error!("Could not pass {:?} through: {:?}", event, e);
+        }
+    }
+}
+
+/// Keys the virtual keyboard declares, every key when none are given
+fn declared_keys(keys: &[KeyCode]) -> Vec<KeyCode> {
+    match keys.is_empty() {
+        true => KeyCode::all()
+            .filter(|code| declares(keys, *code))
+            .collect(),
+        false => keys.to_vec(),
+    }
+}
+
+/// Whether the virtual keyboard may declare `code`, given or cloned. Buttons,
+/// of mice, joysticks and gamepads alike, are left out unless passed through,
+/// so it is not taken for one of those.
+fn declares(keys: &[KeyCode], code: KeyCode) -> bool {
+    keys.contains(&code) || !code.as_ref().starts_with("BTN_")
+}
+
+impl Middleware for Passthrough {
+    fn process(
+        &mut self,
+        event: KeyEvent,
+        context: &mut Context,
+        output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()> {
+        match event {
+            // The virtual keyboard repeats held keys itself
+            KeyEvent::Press(code, true, _) if self.held.contains(&code) => {}
+            KeyEvent::Press(code, false, _) if self.passes(code, context) => {
+                self.held.insert(code);
+                self.send(event);
+            }
+            KeyEvent::Release(code, _) if self.held.remove(&code) => self.send(event),
+            event => output.push(event),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::key_event::key_code::KeyCode;
+    use crate::pipeline::passthrough::{declared_keys, declares};
+
+    #[test]
+    fn test_no_buttons_declared() {
+        let declared = declared_keys(&[]);
+
+        assert!(declared.contains(&KeyCode::A));
+        assert!(declared.contains(&KeyCode::MUTE));
+        for button in [0x110, 0x130, 0x220, 0x2c0, 0x2e7] {
+            assert!(!declared.contains(&KeyCode::from_repr(button).unwrap()));
+        }
+        assert_eq!(declared_keys(&[KeyCode::MUTE]), vec![KeyCode::MUTE]);
+
+        // Keys of a cloned keyboard are kept, its buttons only when passed
+        assert!(declares(&[KeyCode::MUTE], KeyCode::A));
+        assert!(!declares(&[KeyCode::MUTE], KeyCode::BTN_LEFT));
+        assert!(declares(&[KeyCode::BTN_LEFT], KeyCode::BTN_LEFT));
+    }
+}
@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+
+use crate::key_event::key_code::KeyCode;
+use crate::key_event::KeyEvent;
+use crate::pipeline::{Context, Middleware};
+
+/// Turns keys into others, before they are looked up in the layout
+pub struct Remap(HashMap<KeyCode, KeyCode>);
+
+impl Remap {
+    pub fn new(keys: HashMap<KeyCode, KeyCode>) -> Self {
+        Self(keys)
+    }
+
+    fn remap(&self, code: KeyCode) -> KeyCode {
+        self.0.get(&code).copied().unwrap_or(code)
+    }
+}
+
+impl Middleware for Remap {
+    fn process(
+        &mut self,
+        event: KeyEvent,
+        _context: &mut Context,
+        output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()> {
+        output.push(match event {
+            KeyEvent::Press(code, repeat, time) => KeyEvent::Press(self.remap(code), repeat, time),
+            KeyEvent::Release(code, time) => KeyEvent::Release(self.remap(code), time),
+            event => event,
+        });
+
+        Ok(())
+    }
+}
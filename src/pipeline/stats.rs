@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info};
+
+use crate::config::StatsConfig;
+use crate::key_event::KeyEvent;
+use crate::pipeline::{Context, Middleware};
+use crate::stats::Stats;
+
+/// How often recorded statistics are written to their file, besides when the
+/// stage finishes
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Records key usage on top of what was read from the statistics file, passing
+/// every event on
+pub struct StatsStage {
+    stats: Stats,
+    file: PathBuf,
+    saved: Instant,
+    /// Set while a window pausing statistics has the focus, or while the
+    /// focused window is unknown
+    paused: Arc<AtomicBool>,
+}
+
+impl StatsStage {
+    pub fn new(config: &StatsConfig, paused: Arc<AtomicBool>) -> anyhow::Result<Self> {
+        let stats = Stats::from_file(&config.file).map_err(|e| {
+            anyhow::anyhow!("invalid statistics file {}: {e}", config.file.display())
+        })?;
+        info!("Recording statistics to {}", config.file.display());
+
+        Ok(Self {
+            stats,
+            file: config.file.clone(),
+            saved: Instant::now(),
+            paused,
+        })
+    }
+
+    fn save(&mut self) {
+        match self.stats.save(&self.file) {
+            Ok(()) => debug!("Saved statistics to {}", self.file.display()),
+            Err(e) => error!("Could not save statistics: {:?}", e),
+        }
+        self.saved = Instant::now();
+    }
+}
+
+impl Middleware for StatsStage {
+    fn process(
+        &mut self,
+        event: KeyEvent,
+        context: &mut Context,
+        output: &mut Vec<KeyEvent>,
+    ) -> anyhow::Result<()> {
+        match event {
+            KeyEvent::Press(code, false, time) if !self.paused.load(Ordering::Relaxed) => {
+                self.stats
+                    .press(context.mapper.positions(code), context.layer, time);
+                if self.saved.elapsed() >= SAVE_INTERVAL {
+                    self.save();
+                }
+            }
+            KeyEvent::Release(code, time) => {
+                self.stats.release(context.mapper.positions(code), time);
+            }
+            _ => {}
+        }
+
+        output.push(event);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        self.save();
+    }
+}
@@ -0,0 +1,710 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use log::{debug, error};
+use serde::Deserialize;
+
+use crate::key_event::key_code::{KeyCode, MODIFIERS};
+use crate::key_event::matix_mapper::{Layout, MatrixPosition};
+use crate::key_event::KeyEvent;
+use crate::keyboard::{Backend, KeyboardResponse, Operation, Result};
+use crate::uinput::{Identity, UinputDevice};
+
+/// Milliseconds a mod-tap or layer-tap key can be held and still be a tap
+pub const DEFAULT_TAPPING_TERM: u64 = 200;
+
+/// QMK supports 32 layers, which fit in a `u32` like its `layer_state`
+const MAX_LAYERS: usize = 32;
+
+/// QMK basic keycode names, without `KC_`, that are not evdev key names
+const QMK_ALIASES: [(&str, KeyCode); 56] = [
+    ("ENT", KeyCode::ENTER),
+    ("ESCAPE", KeyCode::ESC),
+    ("BSPC", KeyCode::BACKSPACE),
+    ("SPC", KeyCode::SPACE),
+    ("MINS", KeyCode::MINUS),
+    ("EQL", KeyCode::EQUAL),
+    ("LBRC", KeyCode::LEFTBRACE),
+    ("RBRC", KeyCode::RIGHTBRACE),
+    ("BSLS", KeyCode::BACKSLASH),
+    ("NUHS", KeyCode::BACKSLASH),
+    ("SCLN", KeyCode::SEMICOLON),
+    ("QUOT", KeyCode::APOSTROPHE),
+    ("GRV", KeyCode::GRAVE),
+    ("COMM", KeyCode::COMMA),
+    ("SLSH", KeyCode::SLASH),
+    ("NUBS", KeyCode::KEY_102ND),
+    ("CAPS", KeyCode::CAPSLOCK),
+    ("PSCR", KeyCode::SYSRQ),
+    ("SCRL", KeyCode::SCROLLLOCK),
+    ("PAUS", KeyCode::PAUSE),
+    ("INS", KeyCode::INSERT),
+    ("DEL", KeyCode::DELETE),
+    ("PGUP", KeyCode::PAGEUP),
+    ("PGDN", KeyCode::PAGEDOWN),
+    ("RGHT", KeyCode::RIGHT),
+    ("APP", KeyCode::COMPOSE),
+    ("NUM", KeyCode::NUMLOCK),
+    ("PSLS", KeyCode::KPSLASH),
+    ("PAST", KeyCode::KPASTERISK),
+    ("PMNS", KeyCode::KPMINUS),
+    ("PPLS", KeyCode::KPPLUS),
+    ("PENT", KeyCode::KPENTER),
+    ("PDOT", KeyCode::KPDOT),
+    ("P0", KeyCode::KP0),
+    ("P1", KeyCode::KP1),
+    ("P2", KeyCode::KP2),
+    ("P3", KeyCode::KP3),
+    ("P4", KeyCode::KP4),
+    ("P5", KeyCode::KP5),
+    ("P6", KeyCode::KP6),
+    ("P7", KeyCode::KP7),
+    ("P8", KeyCode::KP8),
+    ("P9", KeyCode::KP9),
+    ("LCTL", KeyCode::LEFTCTRL),
+    ("LSFT", KeyCode::LEFTSHIFT),
+    ("LALT", KeyCode::LEFTALT),
+    ("LGUI", KeyCode::LEFTMETA),
+    ("RCTL", KeyCode::RIGHTCTRL),
+    ("RSFT", KeyCode::RIGHTSHIFT),
+    ("RALT", KeyCode::RIGHTALT),
+    ("RGUI", KeyCode::RIGHTMETA),
+    ("MUTE", KeyCode::MUTE),
+    ("VOLU", KeyCode::VOLUMEUP),
+    ("VOLD", KeyCode::VOLUMEDOWN),
+    ("MPLY", KeyCode::PLAYPAUSE),
+    ("MNXT", KeyCode::NEXTSONG),
+];
+
+/// Modifier names of `MT()`, and the prefixes of their `_T()` shorthands
+const MOD_NAMES: [(&str, &str, u8); 8] = [
+    ("MOD_LCTL", "LCTL", 0x01),
+    ("MOD_LSFT", "LSFT", 0x02),
+    ("MOD_LALT", "LALT", 0x04),
+    ("MOD_LGUI", "LGUI", 0x08),
+    ("MOD_RCTL", "RCTL", 0x10),
+    ("MOD_RSFT", "RSFT", 0x20),
+    ("MOD_RALT", "RALT", 0x40),
+    ("MOD_RGUI", "RGUI", 0x80),
+];
+
+/// What a key does on a layer, a subset of the QMK keycodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// `KC_NO` / `XXXXXXX`, does nothing
+    None,
+    /// `KC_TRNS` / `_______`, the next active layer below decides
+    Transparent,
+    Key(KeyCode),
+    /// `MO(layer)`, the layer is on while the key is held
+    Momentary(u8),
+    /// `TG(layer)`
+    Toggle(u8),
+    /// `TO(layer)`, turns every other layer but the base one off
+    To(u8),
+    /// `MT(mods, key)`, the key when tapped and the modifiers when held
+    ModTap(u8, KeyCode),
+    /// `LT(layer, key)`, the key when tapped and `MO(layer)` when held
+    LayerTap(u8, KeyCode),
+}
+
+/// Parses a QMK basic keycode, or an evdev key name
+fn parse_key(name: &str) -> anyhow::Result<KeyCode> {
+    let name = name.trim();
+    let short = name.strip_prefix("KC_").unwrap_or(name);
+
+    QMK_ALIASES
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(short))
+        .map(|(_, code)| *code)
+        .or_else(|| KeyCode::from_str(short).ok())
+        .ok_or_else(|| anyhow!("unknown key {name}"))
+}
+
+fn parse_layer(layer: &str) -> anyhow::Result<u8> {
+    match layer.trim().parse::<u8>() {
+        Ok(layer) if (layer as usize) < MAX_LAYERS => Ok(layer),
+        _ => bail!("invalid layer {layer}, use 0 to {}", MAX_LAYERS - 1),
+    }
+}
+
+fn parse_mods(mods: &str) -> anyhow::Result<u8> {
+    mods.split('|').try_fold(0, |bits, name| {
+        let name = name.trim();
+        MOD_NAMES
+            .iter()
+            .find(|(mod_name, ..)| *mod_name == name)
+            .map(|(.., bit)| bits | bit)
+            .ok_or_else(|| anyhow!("unknown modifier {name}"))
+    })
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+
+        let Some((function, args)) = s.strip_suffix(')').and_then(|s| s.split_once('(')) else {
+            return match s {
+                "KC_NO" | "XXXXXXX" => Ok(Self::None),
+                "KC_TRNS" | "KC_TRANSPARENT" | "_______" => Ok(Self::Transparent),
+                key => Ok(Self::Key(parse_key(key)?)),
+            };
+        };
+
+        let two_args = || {
+            args.split_once(',')
+                .ok_or_else(|| anyhow!("{function}() takes two arguments in {s}"))
+        };
+
+        match function {
+            "MO" => Ok(Self::Momentary(parse_layer(args)?)),
+            "TG" => Ok(Self::Toggle(parse_layer(args)?)),
+            "TO" => Ok(Self::To(parse_layer(args)?)),
+            "LT" => {
+                let (layer, key) = two_args()?;
+                Ok(Self::LayerTap(parse_layer(layer)?, parse_key(key)?))
+            }
+            "MT" => {
+                let (mods, key) = two_args()?;
+                Ok(Self::ModTap(parse_mods(mods)?, parse_key(key)?))
+            }
+            shorthand => match MOD_NAMES
+                .iter()
+                .find(|(_, prefix, _)| shorthand.strip_suffix("_T") == Some(*prefix))
+            {
+                Some((.., bit)) => Ok(Self::ModTap(*bit, parse_key(args)?)),
+                None => bail!("unsupported keycode {s}"),
+            },
+        }
+    }
+}
+
+/// A host-side keymap, read from a QMK `keymap.json`. Each layer lists an
+/// action for every key of the layout, in layout order.
+#[derive(Debug, Deserialize)]
+pub struct Keymap {
+    pub layers: Vec<Vec<String>>,
+}
+
+impl Keymap {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// Runs a keymap on the host, turning matrix updates into key presses and
+/// releases the way QMK would. Mod-tap and layer-tap keys become holds when
+/// another key is pressed before they are released, or once they were held
+/// for the tapping term.
+pub struct SoftwareKeyboard {
+    layers: Vec<HashMap<MatrixPosition, Action>>,
+    tapping_term: Duration,
+    /// Bit n is set when layer n is on, the base layer 0 always is
+    layer_state: u32,
+    /// Action each held position resolved to when pressed. Mod-tap and
+    /// layer-tap keys are only here once they are held.
+    held: HashMap<MatrixPosition, Action>,
+    /// The mod-tap or layer-tap key that is neither a tap nor a hold yet
+    pending: Option<(MatrixPosition, Action, Instant)>,
+}
+
+impl SoftwareKeyboard {
+    pub fn new(keymap: &Keymap, layout: &Layout, tapping_term: Duration) -> anyhow::Result<Self> {
+        if keymap.layers.is_empty() || keymap.layers.len() > MAX_LAYERS {
+            bail!("the keymap needs 1 to {MAX_LAYERS} layers");
+        }
+
+        let layers = keymap
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(index, layer)| {
+                if layer.len() != layout.layout.len() {
+                    bail!(
+                        "layer {index} has {} keys but the layout has {}",
+                        layer.len(),
+                        layout.layout.len()
+                    );
+                }
+
+                layout
+                    .layout
+                    .iter()
+                    .zip(layer)
+                    .map(|(item, keycode)| {
+                        let action: Action = keycode
+                            .parse()
+                            .map_err(|e| anyhow!("layer {index}, key {}: {e}", item.label))?;
+
+                        match action {
+                            Action::Momentary(l)
+                            | Action::Toggle(l)
+                            | Action::To(l)
+                            | Action::LayerTap(l, _)
+                                if l as usize >= keymap.layers.len() =>
+                            {
+                                bail!(
+                                    "layer {index}, key {}: {keycode} refers to a missing layer",
+                                    item.label
+                                )
+                            }
+                            action => Ok((item.matrix, action)),
+                        }
+                    })
+                    .collect()
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            layers,
+            tapping_term,
+            layer_state: 1,
+            held: HashMap::new(),
+            pending: None,
+        })
+    }
+
+    /// Every key the keymap can send
+    pub fn keys(&self) -> Vec<KeyCode> {
+        let mut keys: Vec<KeyCode> = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.values())
+            .filter_map(|action| match action {
+                Action::Key(code) | Action::ModTap(_, code) | Action::LayerTap(_, code) => {
+                    Some(*code)
+                }
+                _ => None,
+            })
+            .chain(MODIFIERS)
+            .collect();
+        keys.sort_by_key(|code| u16::from(*code));
+        keys.dedup();
+
+        keys
+    }
+
+    /// Highest layer that is on
+    pub fn layer(&self) -> u8 {
+        (31 - self.layer_state.leading_zeros()) as u8
+    }
+
+    /// Turns every layer but the base one and `layer` off, like QMK's
+    /// `layer_move`
+    pub fn move_to_layer(&mut self, layer: u8) {
+        if (layer as usize) < self.layers.len() {
+            self.layer_state = 1 | 1 << layer;
+        }
+    }
+
+    /// The action at `position` on the highest layer that is on and does not
+    /// make it transparent
+    fn resolve(&self, position: MatrixPosition) -> Action {
+        (0..self.layers.len())
+            .rev()
+            .filter(|layer| self.layer_state & 1 << layer != 0)
+            .filter_map(|layer| self.layers[layer].get(&position))
+            .find(|action| **action != Action::Transparent)
+            .copied()
+            .unwrap_or(Action::None)
+    }
+
+    /// Returns the keys to press (true) and release (false), in order
+    pub fn press(&mut self, position: MatrixPosition, now: Instant) -> Vec<(KeyCode, bool)> {
+        let mut events = Vec::new();
+
+        if let Some((pending, action, _)) = self.pending.take() {
+            self.hold(pending, action, &mut events);
+        }
+
+        let action = self.resolve(position);
+        match action {
+            Action::Key(code) => events.push((code, true)),
+            Action::Momentary(layer) => self.layer_state |= 1 << layer,
+            // The base layer stays on, as in QMK
+            Action::Toggle(layer) => self.layer_state = (self.layer_state ^ 1 << layer) | 1,
+            Action::To(layer) => self.move_to_layer(layer),
+            Action::ModTap(..) | Action::LayerTap(..) => {
+                self.pending = Some((position, action, now));
+                return events;
+            }
+            Action::None | Action::Transparent => {}
+        }
+        self.held.insert(position, action);
+
+        events
+    }
+
+    /// Returns the keys to press (true) and release (false), in order
+    pub fn release(&mut self, position: MatrixPosition, now: Instant) -> Vec<(KeyCode, bool)> {
+        let mut events = Vec::new();
+
+        if let Some((pending, action, since)) = self.pending {
+            if pending == position {
+                self.pending = None;
+
+                if now.duration_since(since) < self.tapping_term {
+                    let (Action::ModTap(_, code) | Action::LayerTap(_, code)) = action else {
+                        unreachable!("only tap-hold keys are pending");
+                    };
+                    events.extend([(code, true), (code, false)]);
+                    return events;
+                }
+
+                self.hold(position, action, &mut events);
+            }
+        }
+
+        match self.held.remove(&position) {
+            Some(Action::Key(code)) => events.push((code, false)),
+            Some(Action::Momentary(layer) | Action::LayerTap(layer, _)) => {
+                self.layer_state &= !(1 << layer) | 1;
+            }
+            Some(Action::ModTap(mods, _)) => {
+                events.extend(modifiers(mods).map(|code| (code, false)));
+            }
+            _ => {}
+        }
+
+        events
+    }
+
+    /// When the pending tap-hold key becomes a hold, if it is still held
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, _, since)| since + self.tapping_term)
+    }
+
+    /// Makes the pending tap-hold key a hold once it was held for the tapping
+    /// term, returning the keys to press
+    pub fn tick(&mut self, now: Instant) -> Vec<(KeyCode, bool)> {
+        let mut events = Vec::new();
+
+        if let Some((position, action, since)) = self.pending {
+            if now.duration_since(since) >= self.tapping_term {
+                self.pending = None;
+                self.hold(position, action, &mut events);
+            }
+        }
+
+        events
+    }
+
+    /// Makes a pending tap-hold key a hold
+    fn hold(
+        &mut self,
+        position: MatrixPosition,
+        action: Action,
+        events: &mut Vec<(KeyCode, bool)>,
+    ) {
+        match action {
+            Action::ModTap(mods, _) => events.extend(modifiers(mods).map(|code| (code, true))),
+            Action::LayerTap(layer, _) => self.layer_state |= 1 << layer,
+            _ => {}
+        }
+        self.held.insert(position, action);
+    }
+}
+
+fn modifiers(mods: u8) -> impl Iterator<Item = KeyCode> {
+    MODIFIERS
+        .into_iter()
+        .enumerate()
+        .filter(move |(bit, _)| mods & 1 << bit != 0)
+        .map(|(_, code)| code)
+}
+
+/// A `SoftwareKeyboard` and the uinput keyboard it types through
+struct Typist {
+    keyboard: SoftwareKeyboard,
+    device: UinputDevice,
+}
+
+impl Typist {
+    fn send(&mut self, events: Vec<(KeyCode, bool)>) -> Result<()> {
+        for (code, pressed) in events {
+            self.device.send_event(match pressed {
+                true => KeyEvent::Press(code, false, Default::default()),
+                false => KeyEvent::Release(code, Default::default()),
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs a `SoftwareKeyboard` in place of the controller, typing through a
+/// uinput keyboard. A thread makes tap-hold keys holds once the tapping term
+/// passes, as no matrix update may come until then.
+pub struct SoftwareBackend {
+    typist: Arc<Mutex<Typist>>,
+    /// Tells the thread when the pending tap-hold key becomes a hold
+    deadlines: Sender<Instant>,
+}
+
+impl SoftwareBackend {
+    pub fn new(keyboard: SoftwareKeyboard, identity: &Identity) -> anyhow::Result<Self> {
+        let device = identity
+            .keyboard("qmk-virtual-keyboard")
+            .keys(keyboard.keys())
+            .build()?;
+        let typist = Arc::new(Mutex::new(Typist { keyboard, device }));
+        let (deadlines, rx) = channel();
+        watch_tapping_term(typist.clone(), rx);
+
+        Ok(Self { typist, deadlines })
+    }
+}
+
+/// Waits for the deadlines sent to `rx`, letting the keyboard of `typist` make
+/// its pending tap-hold key a hold once each passes. Stops with the backend.
+fn watch_tapping_term(typist: Arc<Mutex<Typist>>, rx: Receiver<Instant>) {
+    std::thread::spawn(move || {
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            let next = match deadline {
+                Some(deadline) => {
+                    rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match next {
+                Ok(next) => deadline = Some(next),
+                Err(RecvTimeoutError::Timeout) => {
+                    deadline = None;
+
+                    let mut typist = typist.lock().expect("typist lock poisoned");
+                    let events = typist.keyboard.tick(Instant::now());
+                    if let Err(e) = typist.send(events) {
+                        error!("Could not type a held tap-hold key: {:?}", e);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+}
+
+impl Backend for SoftwareBackend {
+    fn send_message(&mut self, operation: Operation) -> Result<KeyboardResponse> {
+        let now = Instant::now();
+        let mut typist = self.typist.lock().expect("typist lock poisoned");
+        let Typist { keyboard, .. } = &mut *typist;
+        let events = match operation {
+            Operation::UpdateMatrix(true, row, col) => {
+                keyboard.press(MatrixPosition { row, col }, now)
+            }
+            Operation::UpdateMatrix(false, row, col) => {
+                keyboard.release(MatrixPosition { row, col }, now)
+            }
+            Operation::ChangeLayer(layer) => {
+                keyboard.move_to_layer(layer);
+                return Ok(KeyboardResponse::CurrentLayer(keyboard.layer()));
+            }
+            Operation::GetLayer => return Ok(KeyboardResponse::CurrentLayer(keyboard.layer())),
+            Operation::Bootloader | Operation::EncoderTurn(..) | Operation::HostReports(_) => {
+                debug!("{:?} needs a controller, ignoring it", operation);
+                Vec::new()
+            }
+        };
+
+        if let Some(deadline) = keyboard.deadline() {
+            // Only fails once the thread panicked
+            let _ = self.deadlines.send(deadline);
+        }
+        typist.send(events)?;
+
+        Ok(KeyboardResponse::None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::key_event::key_code::KeyCode;
+    use crate::key_event::matix_mapper::{Layout, MatrixPosition};
+    use crate::software_keyboard::{Action, Keymap, SoftwareKeyboard};
+    use crate::test_fixtures::abc_layout;
+
+    fn keyboard(layers: &str) -> SoftwareKeyboard {
+        let keymap: Keymap = serde_json::from_str(layers).unwrap();
+
+        SoftwareKeyboard::new(&keymap, &abc_layout(), Duration::from_millis(200)).unwrap()
+    }
+
+    const A: MatrixPosition = MatrixPosition { row: 0, col: 0 };
+    const B: MatrixPosition = MatrixPosition { row: 0, col: 1 };
+    const C: MatrixPosition = MatrixPosition { row: 0, col: 2 };
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("KC_A".parse::<Action>().unwrap(), Action::Key(KeyCode::A));
+        assert_eq!(
+            "KC_ENT".parse::<Action>().unwrap(),
+            Action::Key(KeyCode::ENTER)
+        );
+        assert_eq!(
+            "LEFTSHIFT".parse::<Action>().unwrap(),
+            Action::Key(KeyCode::LEFTSHIFT)
+        );
+        assert_eq!("_______".parse::<Action>().unwrap(), Action::Transparent);
+        assert_eq!("MO(2)".parse::<Action>().unwrap(), Action::Momentary(2));
+        assert_eq!(
+            "LT(1, KC_SPC)".parse::<Action>().unwrap(),
+            Action::LayerTap(1, KeyCode::SPACE)
+        );
+        assert_eq!(
+            "MT(MOD_LCTL | MOD_LSFT, KC_ESC)".parse::<Action>().unwrap(),
+            Action::ModTap(0x03, KeyCode::ESC)
+        );
+        assert_eq!(
+            "RSFT_T(KC_ENT)".parse::<Action>().unwrap(),
+            Action::ModTap(0x20, KeyCode::ENTER)
+        );
+        assert!("MO(32)".parse::<Action>().is_err());
+        assert!("OSM(MOD_LSFT)".parse::<Action>().is_err());
+        assert!("KC_NOPE".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut keyboard = keyboard(
+            r#"{ "layers": [
+                ["KC_A", "MO(1)", "TG(2)"],
+                ["KC_1", "_______", "XXXXXXX"],
+                ["KC_B", "_______", "_______"]
+            ] }"#,
+        );
+        let now = Instant::now();
+
+        assert_eq!(keyboard.press(B, now), vec![]);
+        assert_eq!(keyboard.layer(), 1);
+        assert_eq!(keyboard.press(A, now), vec![(KeyCode::KEY_1, true)]);
+        assert_eq!(keyboard.release(B, now), vec![]);
+        // Released on the layer it was pressed on
+        assert_eq!(keyboard.release(A, now), vec![(KeyCode::KEY_1, false)]);
+
+        keyboard.press(C, now);
+        keyboard.release(C, now);
+        assert_eq!(keyboard.layer(), 2);
+        assert_eq!(keyboard.press(A, now), vec![(KeyCode::B, true)]);
+        keyboard.release(A, now);
+        keyboard.press(C, now);
+        assert_eq!(keyboard.layer(), 0);
+    }
+
+    #[test]
+    fn test_toggle_base_layer() {
+        let mut keyboard = keyboard(r#"{ "layers": [["TG(0)", "KC_B", "KC_C"]] }"#);
+        let now = Instant::now();
+
+        keyboard.press(A, now);
+        keyboard.release(A, now);
+        assert_eq!(keyboard.layer(), 0);
+        assert_eq!(keyboard.press(B, now), vec![(KeyCode::B, true)]);
+    }
+
+    #[test]
+    fn test_tap_hold() {
+        let mut keyboard = keyboard(
+            r#"{ "layers": [
+                ["LCTL_T(KC_ESC)", "LT(1, KC_SPC)", "KC_C"],
+                ["_______", "_______", "KC_LEFT"]
+            ] }"#,
+        );
+        let start = Instant::now();
+        let quickly = start + Duration::from_millis(50);
+        let slowly = start + Duration::from_millis(300);
+
+        assert_eq!(keyboard.press(A, start), vec![]);
+        assert_eq!(
+            keyboard.release(A, quickly),
+            vec![(KeyCode::ESC, true), (KeyCode::ESC, false)]
+        );
+
+        keyboard.press(A, start);
+        assert_eq!(
+            keyboard.press(C, quickly),
+            vec![(KeyCode::LEFTCTRL, true), (KeyCode::C, true)]
+        );
+        keyboard.release(C, quickly);
+        assert_eq!(
+            keyboard.release(A, quickly),
+            vec![(KeyCode::LEFTCTRL, false)]
+        );
+
+        keyboard.press(B, start);
+        assert_eq!(keyboard.press(C, quickly), vec![(KeyCode::LEFT, true)]);
+        keyboard.release(C, quickly);
+        keyboard.release(B, quickly);
+        assert_eq!(keyboard.layer(), 0);
+
+        keyboard.press(B, start);
+        assert_eq!(keyboard.release(B, slowly), vec![]);
+        assert_eq!(keyboard.layer(), 0);
+    }
+
+    #[test]
+    fn test_lone_hold() {
+        let mut keyboard = keyboard(
+            r#"{ "layers": [
+                ["LCTL_T(KC_ESC)", "LT(1, KC_SPC)", "KC_C"],
+                ["_______", "_______", "KC_LEFT"]
+            ] }"#,
+        );
+        let start = Instant::now();
+        let term = start + Duration::from_millis(200);
+
+        keyboard.press(A, start);
+        assert_eq!(keyboard.deadline(), Some(term));
+        assert_eq!(keyboard.tick(term - Duration::from_millis(1)), vec![]);
+        assert_eq!(keyboard.tick(term), vec![(KeyCode::LEFTCTRL, true)]);
+        assert_eq!(keyboard.deadline(), None);
+        assert_eq!(
+            keyboard.release(A, term + Duration::from_millis(100)),
+            vec![(KeyCode::LEFTCTRL, false)]
+        );
+
+        keyboard.press(B, start);
+        assert_eq!(keyboard.tick(term), vec![]);
+        assert_eq!(keyboard.layer(), 1);
+        keyboard.release(B, term);
+        assert_eq!(keyboard.layer(), 0);
+    }
+
+    #[test]
+    fn test_invalid_keymap() {
+        let layout: Layout = serde_json::from_str(
+            r#"{ "layout": [{ "matrix": [0, 0], "x": 0, "y": 0, "label": "A" }] }"#,
+        )
+        .unwrap();
+
+        for (layers, error) in [
+            (
+                r#"[["KC_A", "KC_B"]]"#,
+                "layer 0 has 2 keys but the layout has 1",
+            ),
+            (
+                r#"[["MO(1)"]]"#,
+                "layer 0, key A: MO(1) refers to a missing layer",
+            ),
+            (r#"[["KC_NOPE"]]"#, "layer 0, key A: unknown key KC_NOPE"),
+        ] {
+            let keymap = Keymap {
+                layers: serde_json::from_str(layers).unwrap(),
+            };
+            let result = SoftwareKeyboard::new(&keymap, &layout, Duration::ZERO);
+            assert_eq!(result.err().unwrap().to_string(), error);
+        }
+    }
+}
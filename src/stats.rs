@@ -0,0 +1,264 @@
+//! Key usage statistics. Only per-key totals are kept, never the order keys
+//! were pressed in nor when, so nothing typed can be read back from them.
+//!
+//! The file is JSON, with one entry per matrix position that was pressed:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "keys": [
+//!     { "matrix": [1, 3], "presses": 1520, "layers": { "0": 1498, "2": 22 }, "held_ms": 136800, "holds": 1519 }
+//!   ]
+//! }
+//! ```
+//!
+//! `layers` counts presses by the layer known to be active at the time,
+//! `held_ms` is the sum of the hold durations of the `holds` presses whose
+//! release was seen.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use anyhow::bail;
+use log::info;
+
+use serde::{Deserialize, Serialize};
+
+use crate::key_event::matix_mapper::MatrixPosition;
+use crate::key_event::MyTime;
+
+/// What is recorded for one matrix position
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyStats {
+    pub matrix: MatrixPosition,
+    pub presses: u64,
+    /// Presses by the layer active at the time, when it was known
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<u8, u64>,
+    /// Sum of the hold durations, in milliseconds
+    pub held_ms: u64,
+    /// Presses whose release was seen, which `held_ms` is the sum of
+    pub holds: u64,
+}
+
+impl KeyStats {
+    pub fn mean_hold_ms(&self) -> Option<u64> {
+        self.held_ms.checked_div(self.holds)
+    }
+}
+
+const FORMAT_VERSION: u32 = 1;
+
+/// The statistics file, positions sorted so it diffs well
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatsFile {
+    version: u32,
+    keys: Vec<KeyStats>,
+}
+
+/// The statistics file as written before it had a version, which also
+/// counted which position followed which
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct UnversionedStatsFile {
+    keys: Vec<KeyStats>,
+    bigrams: serde::de::IgnoredAny,
+}
+
+/// Key usage gathered by the forwarding loop, added to what is already in the
+/// statistics file when saved
+#[derive(Debug, Default)]
+pub struct Stats {
+    keys: HashMap<MatrixPosition, KeyStats>,
+    /// Press times of the held positions, only kept in memory
+    held: HashMap<MatrixPosition, MyTime>,
+}
+
+impl Stats {
+    /// Reads the statistics file, empty when it does not exist yet. A file
+    /// without a version is rewritten right away without its key sequences.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let value: serde_json::Value = serde_json::from_reader(std::fs::File::open(path)?)?;
+        if value.get("version").is_none() {
+            let file: UnversionedStatsFile = serde_json::from_value(value)?;
+            let stats = Self::with_keys(file.keys);
+            stats.save(path)?;
+            info!("Removed the key sequences from {}", path.display());
+
+            return Ok(stats);
+        }
+
+        let file: StatsFile = serde_json::from_value(value)?;
+        if file.version != FORMAT_VERSION {
+            bail!("unsupported statistics format version {}", file.version);
+        }
+
+        Ok(Self::with_keys(file.keys))
+    }
+
+    fn with_keys(keys: Vec<KeyStats>) -> Self {
+        Self {
+            keys: keys.into_iter().map(|k| (k.matrix, k)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Writes to a temporary file only the user can read first, so an
+    /// interrupted save does not lose what was already there
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut temporary = path.to_path_buf().into_os_string();
+        temporary.push(".tmp");
+
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temporary)?;
+        file.write_all(serde_json::to_string_pretty(&self.to_file())?.as_bytes())?;
+        std::fs::rename(&temporary, path)?;
+
+        Ok(())
+    }
+
+    fn to_file(&self) -> StatsFile {
+        let mut keys: Vec<KeyStats> = self.keys.values().cloned().collect();
+        keys.sort_by_key(|k| (k.matrix.row, k.matrix.col));
+
+        StatsFile {
+            version: FORMAT_VERSION,
+            keys,
+        }
+    }
+
+    /// Records a key pressing `positions`
+    pub fn press(&mut self, positions: &[MatrixPosition], layer: Option<u8>, time: MyTime) {
+        for pos in positions {
+            let key = self.keys.entry(*pos).or_insert_with(|| KeyStats {
+                matrix: *pos,
+                ..Default::default()
+            });
+            key.presses += 1;
+            if let Some(layer) = layer {
+                *key.layers.entry(layer).or_default() += 1;
+            }
+            self.held.insert(*pos, time);
+        }
+    }
+
+    pub fn release(&mut self, positions: &[MatrixPosition], time: MyTime) {
+        for pos in positions {
+            if let (Some(pressed), Some(key)) = (self.held.remove(pos), self.keys.get_mut(pos)) {
+                key.held_ms += time.since(pressed).as_millis() as u64;
+                key.holds += 1;
+            }
+        }
+    }
+
+    /// Every key with presses, most pressed first
+    pub fn keys(&self) -> Vec<&KeyStats> {
+        let mut keys: Vec<&KeyStats> = self.keys.values().collect();
+        keys.sort_by_key(|k| (std::cmp::Reverse(k.presses), k.matrix.row, k.matrix.col));
+        keys
+    }
+
+    /// Presses on each layer, when it was known
+    pub fn layers(&self) -> BTreeMap<u8, u64> {
+        let mut layers = BTreeMap::new();
+        for key in self.keys.values() {
+            for (layer, presses) in &key.layers {
+                *layers.entry(*layer).or_default() += presses;
+            }
+        }
+        layers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::key_event::matix_mapper::MatrixPosition;
+    use crate::stats::Stats;
+    use crate::test_fixtures::at;
+
+    #[test]
+    fn test_record_and_save() {
+        let a = MatrixPosition { row: 0, col: 0 };
+        let b = MatrixPosition { row: 1, col: 2 };
+        let mut stats = Stats::default();
+
+        stats.press(&[a], Some(0), at(1000));
+        stats.release(&[a], at(1080));
+        stats.press(&[b, a], Some(1), at(1200));
+        stats.release(&[b, a], at(1300));
+        stats.press(&[a], None, at(1400));
+        stats.press(&[], Some(0), at(1500));
+
+        let keys = stats.keys();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].matrix, a);
+        assert_eq!(keys[0].presses, 3);
+        assert_eq!(keys[0].holds, 2);
+        assert_eq!(keys[0].mean_hold_ms(), Some(90));
+        assert_eq!(keys[1].mean_hold_ms(), Some(100));
+        assert_eq!(stats.layers(), BTreeMap::from([(0, 1), (1, 2)]));
+
+        let path = std::env::temp_dir().join(format!("stats-test-{}.json", std::process::id()));
+        stats.save(&path).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let loaded = Stats::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            saved,
+            serde_json::json!({
+                "version": 1,
+                "keys": [
+                    { "matrix": [0, 0], "presses": 3, "layers": { "0": 1, "1": 1 }, "held_ms": 180, "holds": 2 },
+                    { "matrix": [1, 2], "presses": 1, "layers": { "1": 1 }, "held_ms": 100, "holds": 1 }
+                ]
+            })
+        );
+        assert_eq!(loaded.keys(), stats.keys());
+    }
+
+    #[test]
+    fn test_drop_bigrams_of_unversioned_file() {
+        let path = std::env::temp_dir().join(format!("stats-bigrams-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{
+                "keys": [{ "matrix": [0, 0], "presses": 3, "held_ms": 180, "holds": 2 }],
+                "bigrams": [{ "first": [0, 0], "second": [0, 0], "count": 2 }]
+            }"#,
+        )
+        .unwrap();
+
+        let loaded = Stats::from_file(&path).unwrap();
+        let rewritten: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.keys()[0].presses, 3);
+        assert_eq!(
+            rewritten,
+            serde_json::json!({
+                "version": 1,
+                "keys": [{ "matrix": [0, 0], "presses": 3, "held_ms": 180, "holds": 2 }]
+            })
+        );
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,24 @@
+//! Values shared by the tests of several modules
+
+use crate::key_event::matix_mapper::Layout;
+use crate::key_event::MyTime;
+
+/// Three keys in a row, labelled A, B and C, on matrix positions 0,0 to 0,2
+pub const ABC_LAYOUT: &str = r#"{ "layout": [
+    { "matrix": [0, 0], "x": 0, "y": 0, "label": "A" },
+    { "matrix": [0, 1], "x": 1, "y": 0, "label": "B" },
+    { "matrix": [0, 2], "x": 2, "y": 0, "label": "C" }
+] }"#;
+
+pub fn abc_layout() -> Layout {
+    serde_json::from_str(ABC_LAYOUT).unwrap()
+}
+
+/// An event time, `ms` milliseconds after the epoch
+pub fn at(ms: i64) -> MyTime {
+    nix::libc::timeval {
+        tv_sec: ms / 1000,
+        tv_usec: ms % 1000 * 1000,
+    }
+    .into()
+}
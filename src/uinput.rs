@@ -1,73 +1,194 @@
 use std::io::Write;
 use std::os::fd::AsRawFd;
 
-use nix::libc::ioctl;
+use nix::libc::{c_char, input_id, ioctl};
 
-use crate::key_event::{KeyEvent, EV_KEY};
+use crate::event_input_device::EventDevice;
+use crate::key_event::key_code::{KeyCode, KEY_MAX};
+use crate::key_event::led::LED_MAX;
+use crate::key_event::relative_axis::REL_MAX;
+use crate::key_event::{KeyEvent, SyncKind, EV_KEY, EV_LED, EV_MAX, EV_REL, EV_REP, EV_SYN};
 
 const UI_SET_EVBIT: u64 = 1074025828;
 const UI_SET_KEYBIT: u64 = 1074025829;
+const UI_SET_RELBIT: u64 = 1074025830;
+const UI_SET_LEDBIT: u64 = 1074025833;
 const UI_DEV_SETUP: u64 = 1079792899;
 const UI_DEV_CREATE: u64 = 21761;
 const UI_DEV_DESTROY: u64 = 21762;
 
-const BUS_USB: u16 = 3;
+const BUS_VIRTUAL: u16 = 6;
 
-pub struct UinputKeyboard(std::fs::File);
+pub struct UinputDevice(std::fs::File);
 
-impl UinputKeyboard {
-    pub fn new(
-        name: String,
-        vendor: u16,
-        product: u16,
-        version: u16,
-    ) -> Result<Self, std::io::Error> {
+/// Fails with the OS error of a negative ioctl result, saying which ioctl it was
+fn check(r: i32, ioctl: &str, arg: u16) -> Result<(), std::io::Error> {
+    if r < 0 {
+        let e = std::io::Error::last_os_error();
+        Err(std::io::Error::new(
+            e.kind(),
+            format!("{ioctl} {arg:#x} failed: {e}"),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Declares exactly which events a uinput device sends before creating it
+#[derive(Clone)]
+pub struct UinputBuilder {
+    name: String,
+    id: input_id,
+    keys: Vec<u16>,
+    relative_axes: Vec<u16>,
+    leds: Vec<u16>,
+    autorepeat: bool,
+}
+
+impl UinputBuilder {
+    /// A virtual device sending nothing but sync events
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            id: input_id {
+                bustype: BUS_VIRTUAL,
+                vendor: 0,
+                product: 0,
+                version: 0,
+            },
+            keys: Vec::new(),
+            relative_axes: Vec::new(),
+            leds: Vec::new(),
+            autorepeat: false,
+        }
+    }
+
+    pub fn keys<I: IntoIterator<Item = KeyCode>>(mut self, keys: I) -> Self {
+        self.keys.extend(keys.into_iter().map(u16::from));
+        self
+    }
+
+    /// Keeps only the keys `keep` accepts, including those of a cloned device
+    pub fn retain_keys<F: Fn(KeyCode) -> bool>(mut self, keep: F) -> Self {
+        self.keys
+            .retain(|code| KeyCode::from_repr(*code).is_some_and(&keep));
+        self
+    }
+
+    /// Lets the kernel repeat held keys, as for a real keyboard
+    pub fn autorepeat(mut self, autorepeat: bool) -> Self {
+        self.autorepeat = autorepeat;
+        self
+    }
+
+    /// A device passing itself off as `device`, with its name followed by
+    /// `suffix`, its ids and its capabilities, so the desktop configures both
+    /// the same way
+    pub fn clone_of(device: &EventDevice, suffix: &str) -> Result<Self, std::io::Error> {
+        let mut builder = Self::new(&format!("{}{suffix}", device.name()?));
+        builder.id = device.id()?;
+
+        builder.capabilities_of(device)
+    }
+
+    /// Adds every key, relative axis and LED `device` can send, and
+    /// autorepeat if it has it
+    pub fn capabilities_of(mut self, device: &EventDevice) -> Result<Self, std::io::Error> {
+        let types = device.supported_codes(EV_SYN, EV_MAX)?;
+
+        if types.contains(&EV_KEY) {
+            self.keys.extend(device.supported_codes(EV_KEY, KEY_MAX)?);
+        }
+        if types.contains(&EV_REL) {
+            self.relative_axes
+                .extend(device.supported_codes(EV_REL, REL_MAX)?);
+        }
+        if types.contains(&EV_LED) {
+            self.leds.extend(device.supported_codes(EV_LED, LED_MAX)?);
+        }
+        self.autorepeat |= types.contains(&EV_REP);
+
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<UinputDevice, std::io::Error> {
         let f = std::fs::OpenOptions::new()
             .write(true)
             .open("/dev/uinput")?;
+        let fd = f.as_raw_fd();
 
         let mut uinput_setup = nix::libc::uinput_setup {
-            id: nix::libc::input_id {
-                bustype: BUS_USB,
-                vendor,
-                product,
-                version,
-            },
+            id: self.id,
             name: [0; 80],
             ff_effects_max: 0,
         };
+        for (c, byte) in uinput_setup.name.iter_mut().zip(self.name.bytes().take(79)) {
+            *c = byte as c_char;
+        }
+
+        let capabilities = [
+            (EV_KEY, UI_SET_KEYBIT, "UI_SET_KEYBIT", &self.keys),
+            (EV_REL, UI_SET_RELBIT, "UI_SET_RELBIT", &self.relative_axes),
+            (EV_LED, UI_SET_LEDBIT, "UI_SET_LEDBIT", &self.leds),
+        ];
 
         unsafe {
-            let r = ioctl(f.as_raw_fd(), UI_SET_EVBIT, EV_KEY as u32);
-            if r < 0 {
-                return Err(std::io::Error::last_os_error());
-            }
+            for (event_type, set_bit, set_bit_name, codes) in capabilities {
+                if codes.is_empty() {
+                    continue;
+                }
 
-            for key in 0..255 {
-                ioctl(f.as_raw_fd(), UI_SET_KEYBIT, key);
-                if r < 0 {
-                    return Err(std::io::Error::last_os_error());
+                check(
+                    ioctl(fd, UI_SET_EVBIT, event_type as u32),
+                    "UI_SET_EVBIT",
+                    event_type,
+                )?;
+                for code in codes {
+                    check(ioctl(fd, set_bit, *code as u32), set_bit_name, *code)?;
                 }
             }
 
-            nix::libc::strncpy(uinput_setup.name.as_mut_ptr(), name.as_ptr() as *mut i8, 79);
-
-            let r = ioctl(f.as_raw_fd(), UI_DEV_SETUP, &uinput_setup);
-            if r < 0 {
-                return Err(std::io::Error::last_os_error());
+            if self.autorepeat {
+                check(
+                    ioctl(fd, UI_SET_EVBIT, EV_REP as u32),
+                    "UI_SET_EVBIT",
+                    EV_REP,
+                )?;
             }
 
-            let r = ioctl(f.as_raw_fd(), UI_DEV_CREATE, 0);
-            if r < 0 {
-                return Err(std::io::Error::last_os_error());
-            }
+            check(ioctl(fd, UI_DEV_SETUP, &uinput_setup), "UI_DEV_SETUP", 0)?;
+            check(ioctl(fd, UI_DEV_CREATE, 0), "UI_DEV_CREATE", 0)?;
         }
 
-        Ok(Self(f))
+        Ok(UinputDevice(f))
+    }
+}
+
+/// Suffix of the name of devices cloning another one
+pub const CLONE_SUFFIX: &str = " (qmk-virtual-keyboard)";
+
+/// What the virtual keyboards typing for the daemon pass themselves off as,
+/// either nothing in particular or a grabbed keyboard, so the desktop applies
+/// the same layout, repeat rate and per-device settings to them
+#[derive(Clone, Default)]
+pub struct Identity(Option<UinputBuilder>);
+
+impl Identity {
+    pub fn of(device: &EventDevice) -> Result<Self, std::io::Error> {
+        Ok(Self(Some(UinputBuilder::clone_of(device, CLONE_SUFFIX)?)))
+    }
+
+    /// A builder of a virtual keyboard, named `name` when it clones nothing.
+    /// Autorepeat is always on.
+    pub fn keyboard(&self, name: &str) -> UinputBuilder {
+        self.0
+            .clone()
+            .unwrap_or_else(|| UinputBuilder::new(name))
+            .autorepeat(true)
     }
 }
 
-impl Drop for UinputKeyboard {
+impl Drop for UinputDevice {
     fn drop(&mut self) {
         unsafe {
             ioctl(self.0.as_raw_fd(), UI_DEV_DESTROY);
@@ -75,7 +196,7 @@ impl Drop for UinputKeyboard {
     }
 }
 
-impl UinputKeyboard {
+impl UinputDevice {
     // Sends a single event
     pub fn send_event_only(&mut self, event: KeyEvent) -> anyhow::Result<()> {
         let input_event: nix::libc::input_event = event.into();
@@ -95,6 +216,6 @@ impl UinputKeyboard {
     // Sends an event followed by a sync
     pub fn send_event(&mut self, event: KeyEvent) -> anyhow::Result<()> {
         self.send_event_only(event)?;
-        self.send_event_only(KeyEvent::Sync(0, Default::default()))
+        self.send_event_only(KeyEvent::Sync(SyncKind::Report, 0, Default::default()))
     }
 }